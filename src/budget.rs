@@ -0,0 +1,91 @@
+use std::time::Instant;
+
+/// How much an API call matters, which decides how early it's refused when
+/// the [ApiBudget] runs low.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ApiPriority {
+    /// Replying to a message, keeping the reply in line with edits to it, or
+    /// cleaning up after a deleted one.
+    Primary,
+    /// Extras nobody asked for directly, like reacting to a message.
+    Low,
+}
+
+/// A token bucket limiting how many API calls the bot makes per minute, as a
+/// safety valve against runaway behavior on top of twilight's rate limits.
+///
+/// The bucket starts full and refills steadily over each minute. [Low]
+/// priority calls can't use the last quarter of it, which is kept for
+/// [Primary] ones, so reactions are refused before replies and edits are.
+///
+/// [Low]: ApiPriority::Low
+/// [Primary]: ApiPriority::Primary
+pub struct ApiBudget {
+    per_minute: u32,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl ApiBudget {
+    pub fn new(per_minute: u32, now: Instant) -> Self {
+        Self {
+            per_minute,
+            tokens: f64::from(per_minute),
+            refilled: now,
+        }
+    }
+
+    /// Takes `calls` from the budget at `now`, returning whether there was
+    /// room for them. Nothing is taken if there wasn't.
+    pub fn try_spend(&mut self, calls: u32, priority: ApiPriority, now: Instant) -> bool {
+        let per_minute = f64::from(self.per_minute);
+        let elapsed = now.saturating_duration_since(self.refilled);
+        let refill = elapsed.as_secs_f64() / 60.0 * per_minute;
+        self.tokens = (self.tokens + refill).min(per_minute);
+        self.refilled = now;
+
+        let reserve = match priority {
+            ApiPriority::Primary => 0.0,
+            ApiPriority::Low => per_minute / 4.0,
+        };
+        if self.tokens - f64::from(calls) < reserve {
+            return false;
+        }
+
+        self.tokens -= f64::from(calls);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ApiBudget, ApiPriority};
+
+    #[test]
+    fn low_priority_first() {
+        let start = Instant::now();
+        let mut budget = ApiBudget::new(8, start);
+
+        // Low priority calls stop with a quarter of the budget left...
+        for _ in 0..6 {
+            assert!(budget.try_spend(1, ApiPriority::Low, start));
+        }
+        assert!(!budget.try_spend(1, ApiPriority::Low, start));
+
+        // ...which primary calls can still use
+        assert!(!budget.try_spend(3, ApiPriority::Primary, start));
+        assert!(budget.try_spend(2, ApiPriority::Primary, start));
+        assert!(!budget.try_spend(1, ApiPriority::Primary, start));
+
+        // Half a minute refills half of it, up to the whole budget
+        let later = start + Duration::from_secs(30);
+        assert!(budget.try_spend(2, ApiPriority::Low, later));
+        assert!(!budget.try_spend(1, ApiPriority::Low, later));
+
+        let much_later = start + Duration::from_secs(600);
+        assert!(budget.try_spend(8, ApiPriority::Primary, much_later));
+        assert!(!budget.try_spend(1, ApiPriority::Primary, much_later));
+    }
+}
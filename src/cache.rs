@@ -1,7 +1,7 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Debug, Formatter};
 
-use twilight_model::id::marker::MessageMarker;
+use twilight_model::id::marker::{ChannelMarker, MessageMarker};
 use twilight_model::id::Id;
 
 type MessageId = Id<MessageMarker>;
@@ -10,7 +10,8 @@ type MessageId = Id<MessageMarker>;
 ///
 /// This cache is backed by a ring buffer of a fixed number of ID pairs, and as
 /// it reaches capacity, the eldest element is evicted. Once an entry has been
-/// written, it cannot be removed until it gets popped out by a newer one.
+/// written, it cannot be removed until it gets popped out by a newer one or is
+/// dropped with [retain].
 ///
 /// # Pending Entries
 /// As soon as a message that needs processing is received, it is entered into
@@ -25,101 +26,233 @@ type MessageId = Id<MessageMarker>;
 /// message is deleted, it may also be returned to a pending state via the
 /// [take_entry] method.
 ///
+/// # Generations
+/// Every pending entry is stamped with a generation, which is new each time
+/// the entry is filed or taken. Tokens remember the generation of the entry
+/// they were made for, and a token that doesn't match its entry's is ignored
+/// by [insert], since the entry may have been replaced by a newer one for the
+/// same source. Other entries are unaffected.
+///
+/// # Overflow
+/// A reply split across several messages is entered under its first message,
+/// and the rest are kept to the side with [set_overflow]. They're dropped along
+/// with the entry, whether it's evicted or removed with [retain].
+///
 /// [pending]: CacheEntry::Pending
 /// [take_entry]: ReplyCache::take_entry
-pub struct ReplyCache(VecDeque<(MessageId, CacheEntry)>);
+/// [retain]: ReplyCache::retain
+/// [insert]: ReplyCache::insert
+/// [set_overflow]: ReplyCache::set_overflow
+pub struct ReplyCache {
+    entries: VecDeque<(MessageId, CacheEntry, u64)>,
+    overflow: HashMap<MessageId, Vec<MessageId>>,
+    generation: u64,
+}
 
 impl ReplyCache {
     pub fn with_capacity(capacity: usize) -> Self {
         assert!(capacity > 0, "Cache must have positive capacity");
-        Self(VecDeque::with_capacity(capacity))
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            overflow: HashMap::new(),
+            generation: 0,
+        }
     }
 
     #[inline]
     fn search(&self, source: MessageId) -> Result<usize, usize> {
-        self.0
-            .binary_search_by_key(&source, |&(source, _reply)| source)
+        self.entries
+            .binary_search_by_key(&source, |&(source, _reply, _generation)| source)
+    }
+
+    /// A generation no entry has had yet.
+    #[inline]
+    fn next_generation(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
     }
 
     /// Files a [CacheEntry::Pending] value for the given source message. If the
     /// entry is free, an [InsertToken] is returned. If there is another value
     /// in the source message's slot, `[None]` is returned.
     pub fn file_pending(&mut self, source: MessageId) -> Option<InsertToken> {
-        if self.0.len() == self.0.capacity() {
-            self.0.pop_front();
-        }
-
         // Fast path: messages generally come in order, so we check the tail to
         // see if we can just append
-        if let Some(&(back_source, _entry)) = self.0.back() {
-            if back_source <= source {
-                let idx = self.0.len(); // The push increments len by 1
-                self.0.push_back((source, CacheEntry::Pending));
-                return Some(InsertToken { source, idx });
+        let mut idx = match self.entries.back() {
+            Some(&(back_source, _entry, _generation)) if back_source < source => self.entries.len(),
+            // Err means we have an open slot to insert into
+            _ => self.search(source).err()?,
+        };
+
+        // Only evict once we know the slot is free, so a taken slot doesn't
+        // push out an entry for nothing
+        if self.entries.len() == self.entries.capacity() {
+            if let Some((evicted_source, _evicted, _generation)) = self.entries.pop_front() {
+                self.overflow.remove(&evicted_source);
+
+                // Everything after the front moved down by one
+                idx = idx.saturating_sub(1);
             }
         }
 
-        // Err means we have an open slot to insert into
-        if let Err(idx) = self.search(source) {
-            self.0.insert(idx, (source, CacheEntry::Pending));
-            Some(InsertToken { source, idx })
-        } else {
-            None
-        }
+        let generation = self.next_generation();
+        self.entries
+            .insert(idx, (source, CacheEntry::Pending, generation));
+        Some(InsertToken {
+            source,
+            idx,
+            generation,
+        })
     }
 
-    /// Completes an insertion into the cache after a reply has been sent.
+    /// Completes an insertion into the cache after a reply has been sent. If
+    /// the pending entry has since been removed, or was taken or filed again
+    /// after the token was made, nothing happens.
     pub fn insert(&mut self, token: InsertToken, reply: MessageId) {
+        self.fill(token, CacheEntry::Filled(reply));
+    }
+
+    /// Completes an insertion like [insert], for a reply sent in a thread
+    /// started from the source message.
+    ///
+    /// [insert]: ReplyCache::insert
+    pub fn insert_threaded(&mut self, token: InsertToken, reply: MessageId) {
+        self.fill(token, CacheEntry::Threaded(reply));
+    }
+
+    /// Completes an insertion like [insert], for a reply sent to the
+    /// `mirror_channel` instead of the source's channel.
+    ///
+    /// [insert]: ReplyCache::insert
+    pub fn insert_mirrored(
+        &mut self,
+        token: InsertToken,
+        channel_id: Id<ChannelMarker>,
+        reply: MessageId,
+    ) {
+        self.fill(token, CacheEntry::Mirrored(channel_id, reply));
+    }
+
+    fn fill(&mut self, token: InsertToken, entry: CacheEntry) {
         // The token stores the index it was at when it was made, check if it's
         // still there
-        if let Some(&token_match) = self.0.get(token.idx) {
-            if token_match.0 == token.source {
-                self.0[token.idx] = (token.source, CacheEntry::Filled(reply));
-                return;
-            }
+        let idx = match self.entries.get(token.idx) {
+            Some(&(source, _entry, _generation)) if source == token.source => token.idx,
+            // Fallthrough: another entry has been added since we got the token
+            _ => match self.search(token.source) {
+                Ok(idx) => idx,
+                Err(_) => return,
+            },
+        };
+
+        let slot = &mut self.entries[idx];
+        if slot.2 != token.generation {
+            tracing::debug!("Dropping stale cache insert for {}", token.source);
+            return;
         }
 
-        // Fallthrough: another entry has been added since we got the token
-        let idx = self.search(token.source);
-        let idx = idx.map_or_else(|ok| ok, |err| err);
-        self.0[idx] = (token.source, CacheEntry::Filled(reply));
+        slot.1 = entry;
     }
 
     /// Gets an entry from the cache from the provided source message ID by
     /// binary searching the backing vector.
     pub fn get_entry(&self, source: MessageId) -> Option<CacheEntry> {
-        self.search(source).ok().map(|idx| self.0[idx].1)
+        self.search(source).ok().map(|idx| self.entries[idx].1)
     }
 
     /// Gets an entry from the cache, invalidating it after it has been returned.
     pub fn take_entry(&mut self, source: MessageId) -> Option<CacheEntry> {
         if let Ok(idx) = self.search(source) {
-            let (_, entry) = self.0[idx];
-            self.0[idx] = (source, CacheEntry::Pending);
+            let (_, entry, _) = self.entries[idx];
+            let generation = self.next_generation();
+            self.entries[idx] = (source, CacheEntry::Pending, generation);
             Some(entry)
         } else {
             None
         }
     }
 
+    /// Records the messages after the first of a reply that was split up, in
+    /// the order they were sent. Nothing is recorded if there's no entry for
+    /// `source`.
+    pub fn set_overflow(&mut self, source: MessageId, replies: Vec<MessageId>) {
+        if replies.is_empty() {
+            self.overflow.remove(&source);
+        } else if self.search(source).is_ok() {
+            self.overflow.insert(source, replies);
+        }
+    }
+
+    /// The messages after the first of the reply to `source`, which is empty
+    /// unless it was split up.
+    pub fn overflow(&self, source: MessageId) -> &[MessageId] {
+        self.overflow.get(&source).map_or(&[], Vec::as_slice)
+    }
+
+    /// Gets the messages after the first of the reply to `source`, forgetting
+    /// them.
+    pub fn take_overflow(&mut self, source: MessageId) -> Vec<MessageId> {
+        self.overflow.remove(&source).unwrap_or_default()
+    }
+
+    /// Whether `message` is one of the replies in the cache, including the
+    /// extra messages of split replies. This has to check every entry, since
+    /// they're sorted by source.
+    pub fn is_reply(&self, message: MessageId) -> bool {
+        let first = self.entries.iter().any(|(_, entry, _)| match *entry {
+            CacheEntry::Pending => false,
+            CacheEntry::Filled(reply)
+            | CacheEntry::Threaded(reply)
+            | CacheEntry::Mirrored(_, reply) => reply == message,
+        });
+
+        first
+            || self
+                .overflow
+                .values()
+                .flatten()
+                .any(|&reply| reply == message)
+    }
+
+    /// Iterates over the entries in the cache in order of source message ID.
+    pub fn iter(&self) -> impl Iterator<Item = (MessageId, &CacheEntry)> {
+        self.entries
+            .iter()
+            .map(|(source, entry, _)| (*source, entry))
+    }
+
+    /// Removes every entry `f` returns `false` for. The remaining entries stay
+    /// in order.
+    pub fn retain(&mut self, mut f: impl FnMut(MessageId, &CacheEntry) -> bool) {
+        let overflow = &mut self.overflow;
+        self.entries.retain(|(source, entry, _)| {
+            let keep = f(*source, entry);
+            if !keep {
+                overflow.remove(source);
+            }
+            keep
+        });
+    }
+
     /// Test fixture used to check that cache eviction is working.
     #[cfg(test)]
     fn len(&self) -> usize {
-        self.0.len()
+        self.entries.len()
     }
 }
 
 impl Debug for ReplyCache {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ReplyCache")
-            .field("size", &self.0.len())
-            .field("state", &self.0)
+            .field("size", &self.entries.len())
+            .field("generation", &self.generation)
+            .field("state", &self.entries)
+            .field("overflow", &self.overflow)
             .finish()
     }
 }
 
 /// An entry in the [ReplyCache].
-// Thanks to niches this enum does not change the size of the cache at all!
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum CacheEntry {
     /// An incomplete entry that has not received a reply. This state is also
@@ -127,17 +260,42 @@ pub enum CacheEntry {
     Pending,
     /// A filled entry pointing to the bot's reply message ID.
     Filled(MessageId),
+    /// A filled entry pointing to the bot's reply, which was sent in a thread
+    /// started from the source message.
+    Threaded(MessageId),
+    /// A filled entry pointing to the bot's reply, which was sent to another
+    /// channel.
+    Mirrored(Id<ChannelMarker>, MessageId),
+}
+
+impl CacheEntry {
+    /// Gets the channel and ID of the reply, if there is one, given the channel
+    /// and ID of the source message.
+    pub fn reply(
+        self,
+        channel_id: Id<ChannelMarker>,
+        source: MessageId,
+    ) -> Option<(Id<ChannelMarker>, MessageId)> {
+        match self {
+            Self::Pending => None,
+            Self::Filled(reply) => Some((channel_id, reply)),
+            // Threads started from a message share its ID
+            Self::Threaded(reply) => Some((source.cast(), reply)),
+            Self::Mirrored(channel_id, reply) => Some((channel_id, reply)),
+        }
+    }
 }
 
 /// A token indicating that a message has been received and needs a reply but
 /// the reply has not yet been sent.
 ///
-/// This class contains the source message ID and the speculative index of
-/// where the entry will end up.
+/// This class contains the source message ID, the speculative index of where
+/// the entry will end up, and the generation of the entry it was made for.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct InsertToken {
     source: MessageId,
     idx: usize,
+    generation: u64,
 }
 
 #[cfg(test)]
@@ -194,4 +352,205 @@ mod tests {
         assert_eq!(cache.len(), 4);
         assert_eq!(cache.get_entry(id!(5)), Some(CacheEntry::Filled(id!(15))));
     }
+
+    #[test]
+    fn iter_and_retain() {
+        let mut cache = ReplyCache::with_capacity(8);
+        let id = |id| super::MessageId::new(id);
+
+        for source in [3, 1, 4, 2, 5] {
+            let token = cache.file_pending(id(source)).unwrap();
+            if source % 2 == 1 {
+                cache.insert(token, id(source + 10));
+            }
+        }
+
+        let sources: Vec<_> = cache.iter().map(|(source, _)| source.get()).collect();
+        assert_eq!(sources, [1, 2, 3, 4, 5]);
+
+        // Drop the pending entries
+        cache.retain(|_, entry| *entry != CacheEntry::Pending);
+        let entries: Vec<_> = cache.iter().map(|(s, e)| (s.get(), *e)).collect();
+        assert_eq!(
+            entries,
+            [
+                (1, CacheEntry::Filled(id(11))),
+                (3, CacheEntry::Filled(id(13))),
+                (5, CacheEntry::Filled(id(15))),
+            ]
+        );
+
+        // Still sorted, so new entries land in the right place
+        let token = cache.file_pending(id(2)).unwrap();
+        cache.insert(token, id(12));
+        let sources: Vec<_> = cache.iter().map(|(source, _)| source.get()).collect();
+        assert_eq!(sources, [1, 2, 3, 5]);
+
+        // Filling an entry that was removed in the meantime does nothing
+        let token = cache.file_pending(id(6)).unwrap();
+        cache.retain(|source, _| source != id(6));
+        cache.insert(token, id(16));
+        assert_eq!(cache.get_entry(id(6)), None);
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn insert_after_eviction() {
+        let mut cache = ReplyCache::with_capacity(4);
+        let id = |id| super::MessageId::new(id);
+
+        for source in [2, 4, 6, 8] {
+            let token = cache.file_pending(id(source)).unwrap();
+            cache.insert(token, id(source + 10));
+        }
+
+        // Out of order, so 2 is evicted and 5 lands in the middle
+        let token = cache.file_pending(id(5)).unwrap();
+        cache.insert(token, id(15));
+        let sources: Vec<_> = cache.iter().map(|(source, _)| source.get()).collect();
+        assert_eq!(sources, [4, 5, 6, 8]);
+        assert_eq!(cache.get_entry(id(5)), Some(CacheEntry::Filled(id(15))));
+
+        // Older than everything left, so it goes at the front
+        let token = cache.file_pending(id(3)).unwrap();
+        cache.insert(token, id(13));
+        let sources: Vec<_> = cache.iter().map(|(source, _)| source.get()).collect();
+        assert_eq!(sources, [3, 5, 6, 8]);
+        assert_eq!(cache.get_entry(id(3)), Some(CacheEntry::Filled(id(13))));
+
+        // Two out of order before either is filled, each evicting the front
+        let token_7 = cache.file_pending(id(7)).unwrap();
+        let token_4 = cache.file_pending(id(4)).unwrap();
+        cache.insert(token_7, id(17));
+        cache.insert(token_4, id(14));
+        assert_eq!(cache.get_entry(id(7)), Some(CacheEntry::Filled(id(17))));
+        assert_eq!(cache.get_entry(id(4)), Some(CacheEntry::Filled(id(14))));
+
+        // Taken slots, including the tail, don't evict or duplicate anything
+        let before: Vec<_> = cache.iter().map(|(s, e)| (s, *e)).collect();
+        assert_eq!(cache.file_pending(id(8)), None);
+        assert_eq!(cache.file_pending(id(6)), None);
+        let after: Vec<_> = cache.iter().map(|(s, e)| (s, *e)).collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn duplicate_source() {
+        let mut cache = ReplyCache::with_capacity(4);
+        let id = |id| super::MessageId::new(id);
+
+        // The same snowflake as the tail, whether it's still pending or not
+        let token = cache.file_pending(id(1)).unwrap();
+        assert_eq!(cache.file_pending(id(1)), None);
+        cache.insert(token, id(11));
+        assert_eq!(cache.file_pending(id(1)), None);
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get_entry(id(1)), Some(CacheEntry::Filled(id(11))));
+    }
+
+    #[test]
+    fn stale_token() {
+        let mut cache = ReplyCache::with_capacity(4);
+        let id = |id| super::MessageId::new(id);
+
+        // The source is deleted while the reply is in flight
+        let token = cache.file_pending(id(1)).unwrap();
+        assert_eq!(cache.take_entry(id(1)), Some(CacheEntry::Pending));
+        cache.insert(token, id(11));
+        assert_eq!(cache.get_entry(id(1)), Some(CacheEntry::Pending));
+
+        // The entry is dropped and filed again, and the old token comes back
+        let stale = cache.file_pending(id(2)).unwrap();
+        cache.retain(|source, _| source != id(2));
+        let fresh = cache.file_pending(id(2)).unwrap();
+
+        cache.insert(stale, id(12));
+        assert_eq!(cache.get_entry(id(2)), Some(CacheEntry::Pending));
+        cache.insert(fresh, id(22));
+        assert_eq!(cache.get_entry(id(2)), Some(CacheEntry::Filled(id(22))));
+    }
+
+    #[test]
+    fn unrelated_stale_token() {
+        let mut cache = ReplyCache::with_capacity(2);
+        let id = |id| super::MessageId::new(id);
+
+        // Other pending entries being taken, dropped, or evicted doesn't
+        // invalidate a token
+        let token = cache.file_pending(id(2)).unwrap();
+        cache.file_pending(id(3)).unwrap();
+        cache.take_entry(id(3));
+        cache.retain(|source, _| source != id(3));
+        cache.file_pending(id(1)).unwrap();
+        cache.file_pending(id(4)).unwrap();
+        assert_eq!(cache.get_entry(id(1)), None);
+
+        cache.insert(token, id(12));
+        assert_eq!(cache.get_entry(id(2)), Some(CacheEntry::Filled(id(12))));
+    }
+
+    #[test]
+    fn reply_location() {
+        let mut cache = ReplyCache::with_capacity(4);
+        let id = |id| super::MessageId::new(id);
+        let channel = super::Id::new(100);
+
+        let token = cache.file_pending(id(1)).unwrap();
+        cache.insert(token, id(11));
+        let token = cache.file_pending(id(2)).unwrap();
+        cache.insert_threaded(token, id(12));
+        let token = cache.file_pending(id(3)).unwrap();
+        cache.insert_mirrored(token, super::Id::new(50), id(13));
+
+        let reply = |source| {
+            cache
+                .get_entry(id(source))
+                .unwrap()
+                .reply(channel, id(source))
+        };
+        assert_eq!(reply(1), Some((channel, id(11))));
+        assert_eq!(reply(2), Some((super::Id::new(2), id(12))));
+        assert_eq!(reply(3), Some((super::Id::new(50), id(13))));
+
+        assert!(cache.is_reply(id(11)));
+        assert!(cache.is_reply(id(12)));
+        assert!(cache.is_reply(id(13)));
+        assert!(!cache.is_reply(id(1)));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut cache = ReplyCache::with_capacity(2);
+        let id = |id| super::MessageId::new(id);
+
+        // Nothing to hang it off of yet
+        cache.set_overflow(id(1), vec![id(21)]);
+        assert!(cache.overflow(id(1)).is_empty());
+
+        let token = cache.file_pending(id(1)).unwrap();
+        cache.insert(token, id(11));
+        cache.set_overflow(id(1), vec![id(21), id(31)]);
+        assert_eq!(cache.overflow(id(1)), [id(21), id(31)]);
+        assert!(cache.is_reply(id(31)));
+
+        assert_eq!(cache.take_overflow(id(1)), [id(21), id(31)]);
+        assert!(cache.overflow(id(1)).is_empty());
+        assert!(!cache.is_reply(id(31)));
+
+        // Removed along with the entry
+        cache.set_overflow(id(1), vec![id(21)]);
+        cache.retain(|source, _| source != id(1));
+        assert!(cache.overflow(id(1)).is_empty());
+
+        // And when it's evicted
+        for source in [2, 3, 4] {
+            let token = cache.file_pending(id(source)).unwrap();
+            cache.insert(token, id(source + 10));
+            cache.set_overflow(id(source), vec![id(source + 20)]);
+        }
+        assert_eq!(cache.get_entry(id(2)), None);
+        assert!(!cache.is_reply(id(22)));
+        assert_eq!(cache.overflow(id(4)), [id(24)]);
+    }
 }
@@ -1,7 +1,18 @@
-use serde::Deserialize;
-use twilight_model::id::{marker::UserMarker, Id};
+use std::fmt::Write;
 
-use crate::pass::Pass;
+use serde::{Deserialize, Deserializer};
+use twilight_model::channel::message::{AllowedMentions, MentionType, MessageFlags};
+use twilight_model::gateway::Intents;
+use twilight_model::id::{
+    marker::{ChannelMarker, UserMarker},
+    Id,
+};
+
+use crate::pass::{
+    ApplyOptions, Pass, QueryPolicy, Rewrite, RewriteMode, SpoilerPropagation, SpoilerRender, Stem,
+    DEFAULT_SPOILER_EMOJI,
+};
+use crate::quiet::QuietHours;
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -10,7 +21,763 @@ pub struct Config {
     #[serde(default)]
     pub ignored_users: Vec<Id<UserMarker>>,
     #[serde(default)]
+    pub ignore_replies: bool,
+    #[serde(default)]
+    pub owners: Vec<Id<UserMarker>>,
+    #[serde(default)]
+    pub maintenance: bool,
+    pub quiet_hours: Option<QuietHours>,
+    #[serde(default)]
     pub suppress_delay_millis: u64,
+    #[serde(default)]
+    pub suppress_jitter_millis: u64,
+    #[serde(default)]
+    pub post_delay_millis: u64,
+    pub reply_ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub edit_out_grace_millis: u64,
+    #[serde(default)]
+    pub edit_coalesce_millis: u64,
+    #[serde(default = "default_reply_rate_limit_retries")]
+    pub reply_rate_limit_retries: u32,
+    pub max_message_age_secs: Option<u64>,
+    pub min_account_age_secs: Option<u64>,
+    pub min_member_age_secs: Option<u64>,
+    #[serde(default = "default_suppress_concurrency")]
+    pub suppress_concurrency: usize,
+    #[serde(default = "default_suppress_flags", deserialize_with = "message_flags")]
+    pub suppress_flags: MessageFlags,
+    #[serde(default)]
+    pub on_suppress_failure: SuppressFailure,
+    #[serde(default, deserialize_with = "allowed_mentions")]
+    pub allowed_mentions: AllowedMentions,
+    #[serde(default)]
+    pub default_query_policy: QueryPolicy,
+    #[serde(default)]
+    pub spoiler_propagation: SpoilerPropagation,
+    #[serde(default)]
+    pub rewrite_mode: RewriteMode,
+    #[serde(default)]
+    pub spoiler_render: SpoilerRender,
+    pub spoiler_emoji: Option<String>,
+    #[serde(default)]
+    pub reply_in_new_thread: bool,
+    #[serde(default)]
+    pub reply_buttons: bool,
+    #[serde(default)]
+    pub delete_original: bool,
+    #[serde(default)]
+    pub keep_filtered_replies: bool,
+    #[serde(default)]
+    pub on_empty_rewrite: EmptyRewrite,
+    #[serde(default)]
+    pub scan_embeds: bool,
+    #[serde(default)]
+    pub topic_directives: bool,
+    pub mirror_channel: Option<Id<ChannelMarker>>,
+    #[serde(default = "default_include_jump_link")]
+    pub include_jump_link: bool,
+    pub dedup_window_secs: Option<u64>,
+    #[serde(default)]
+    pub dedup_reaction: bool,
+    pub join_separator: Option<String>,
+    #[serde(default)]
+    pub dedup_across_passes: bool,
+    #[serde(default)]
+    pub group_by_pass: bool,
+    #[serde(default = "default_skip_already_fixed")]
+    pub skip_already_fixed: bool,
+    #[serde(default)]
+    pub reply_overflow: ReplyOverflow,
+    #[serde(default)]
+    pub history_preload: usize,
+    #[serde(default)]
+    pub trace_events: bool,
+    pub global_api_budget: Option<u32>,
+    #[serde(default, rename = "channel")]
+    pub channels: Vec<ChannelPasses>,
     #[serde(rename = "pass")]
     pub passes: Vec<Pass>,
 }
+
+/// Which passes run in one channel, and how replies there differ from other
+/// channels, from a `[[channel]]` section.
+#[derive(Deserialize)]
+pub struct ChannelPasses {
+    pub id: Id<ChannelMarker>,
+    /// If set, only the passes with these labels run.
+    pub enabled_passes: Option<Vec<String>>,
+    /// The passes with these labels don't run, even if they're enabled.
+    #[serde(default)]
+    pub disabled_passes: Vec<String>,
+    /// If set, used instead of the global `rewrite_mode`.
+    pub rewrite_mode: Option<RewriteMode>,
+    /// If set, used instead of the global `reply_in_new_thread`.
+    pub reply_in_new_thread: Option<bool>,
+}
+
+impl ChannelPasses {
+    /// Whether the pass with this label runs in the channel.
+    pub fn runs(&self, label: &str) -> bool {
+        let enabled = self
+            .enabled_passes
+            .as_ref()
+            .is_none_or(|enabled| enabled.iter().any(|l| l == label));
+        enabled && !self.disabled_passes.iter().any(|l| l == label)
+    }
+}
+
+fn default_suppress_concurrency() -> usize {
+    16
+}
+
+fn default_suppress_flags() -> MessageFlags {
+    MessageFlags::SUPPRESS_EMBEDS
+}
+
+fn default_reply_rate_limit_retries() -> u32 {
+    3
+}
+
+fn default_include_jump_link() -> bool {
+    true
+}
+
+fn default_skip_already_fixed() -> bool {
+    true
+}
+
+/// What to do with a reply too long for one Discord message.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyOverflow {
+    /// Leave out the links that don't fit.
+    #[default]
+    Truncate,
+    /// Send the links that don't fit in more messages after the reply.
+    Split,
+}
+
+/// What to do with a reply when the embeds on its message couldn't be
+/// suppressed, leaving two embeds of the same post.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressFailure {
+    /// Leave the reply as it is.
+    #[default]
+    Keep,
+    /// Delete the reply, or cancel it if it's waiting out `post_delay_millis`.
+    Delete,
+    /// Delete the reply like [SuppressFailure::Delete], and react to the
+    /// message instead.
+    React,
+}
+
+/// What to do with a reply when its message is edited to have no links left.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyRewrite {
+    /// Delete the reply, after `edit_out_grace_millis` if set.
+    #[default]
+    Delete,
+    /// Leave the reply as it is.
+    Keep,
+    /// Replace the reply's content with a placeholder.
+    ClearContent,
+}
+
+/// The intents Discord only grants to bots that have been approved for them.
+pub const PRIVILEGED_INTENTS: Intents = Intents::GUILD_MEMBERS
+    .union(Intents::GUILD_PRESENCES)
+    .union(Intents::MESSAGE_CONTENT);
+
+impl Config {
+    /// The gateway intents needed for the features enabled in this config.
+    /// Reading messages and their content is always required.
+    pub fn intents(&self) -> Intents {
+        let mut intents = Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT;
+        if self.topic_directives || self.history_preload > 0 {
+            // Channel topics and lists come with the guild and channel events
+            intents |= Intents::GUILDS;
+        }
+
+        intents
+    }
+
+    /// The settings passed to every pass when rewriting a message.
+    pub fn apply_options(&self) -> ApplyOptions<'_> {
+        ApplyOptions {
+            default_query: self.default_query_policy,
+            spoilers: self.spoiler_propagation,
+            rewrite_mode: self.rewrite_mode,
+            spoiler_render: self.spoiler_render,
+            spoiler_emoji: self
+                .spoiler_emoji
+                .as_deref()
+                .unwrap_or(DEFAULT_SPOILER_EMOJI),
+            separator: self.join_separator.as_deref().unwrap_or(" "),
+            dedup_across_passes: self.dedup_across_passes,
+            group_by_pass: self.group_by_pass,
+            skip_already_fixed: self.skip_already_fixed,
+        }
+    }
+
+    /// Like [apply_options], but following the `[[channel]]` section for the
+    /// channel if it has one.
+    ///
+    /// [apply_options]: Config::apply_options
+    pub fn apply_options_in(&self, channel_id: Id<ChannelMarker>) -> ApplyOptions<'_> {
+        let mut options = self.apply_options();
+        let section = self.channel(channel_id);
+        if let Some(rewrite_mode) = section.and_then(|section| section.rewrite_mode) {
+            options.rewrite_mode = rewrite_mode;
+        }
+
+        options
+    }
+
+    /// Whether replies in a channel go in a new thread, following its
+    /// `[[channel]]` section if it has one.
+    pub fn reply_in_new_thread_in(&self, channel_id: Id<ChannelMarker>) -> bool {
+        self.channel(channel_id)
+            .and_then(|section| section.reply_in_new_thread)
+            .unwrap_or(self.reply_in_new_thread)
+    }
+
+    /// The `[[channel]]` section for a channel, if it has one.
+    fn channel(&self, channel_id: Id<ChannelMarker>) -> Option<&ChannelPasses> {
+        self.channels
+            .iter()
+            .find(|channel| channel.id == channel_id)
+    }
+
+    /// The passes that run in a channel, following its `[[channel]]` section if
+    /// it has one. Every pass runs in channels without one.
+    pub fn passes_in(&self, channel_id: Id<ChannelMarker>) -> Vec<&Pass> {
+        let section = self.channel(channel_id);
+        self.passes
+            .iter()
+            .filter(|pass| section.is_none_or(|section| section.runs(&pass.label)))
+            .collect()
+    }
+
+    /// Runs only the pass with the given label over `content`, for previewing
+    /// what it does. Returns `None` if there's no pass with that label.
+    pub fn apply_pass(&self, label: &str, content: &str) -> Option<Rewrite> {
+        let pass = self.passes.iter().find(|pass| pass.label == label)?;
+        Some(pass.rewrite(content, self.apply_options()))
+    }
+
+    /// The options that turn on optional behavior and are set in this config,
+    /// by their names in the file.
+    pub fn enabled_features(&self) -> Vec<&'static str> {
+        let features = [
+            ("ignore_replies", self.ignore_replies),
+            ("maintenance", self.maintenance),
+            ("quiet_hours", self.quiet_hours.is_some()),
+            ("suppress_jitter_millis", self.suppress_jitter_millis > 0),
+            ("post_delay_millis", self.post_delay_millis > 0),
+            ("reply_ttl_secs", self.reply_ttl_secs.is_some()),
+            ("edit_out_grace_millis", self.edit_out_grace_millis > 0),
+            ("edit_coalesce_millis", self.edit_coalesce_millis > 0),
+            (
+                "on_suppress_failure",
+                self.on_suppress_failure != SuppressFailure::Keep,
+            ),
+            ("max_message_age_secs", self.max_message_age_secs.is_some()),
+            ("min_account_age_secs", self.min_account_age_secs.is_some()),
+            ("min_member_age_secs", self.min_member_age_secs.is_some()),
+            ("reply_in_new_thread", self.reply_in_new_thread),
+            ("reply_buttons", self.reply_buttons),
+            ("delete_original", self.delete_original),
+            ("keep_filtered_replies", self.keep_filtered_replies),
+            (
+                "on_empty_rewrite",
+                self.on_empty_rewrite != EmptyRewrite::Delete,
+            ),
+            ("scan_embeds", self.scan_embeds),
+            ("topic_directives", self.topic_directives),
+            ("mirror_channel", self.mirror_channel.is_some()),
+            ("dedup_window_secs", self.dedup_window_secs.is_some()),
+            ("dedup_reaction", self.dedup_reaction),
+            ("dedup_across_passes", self.dedup_across_passes),
+            ("group_by_pass", self.group_by_pass),
+            ("history_preload", self.history_preload > 0),
+            ("trace_events", self.trace_events),
+            ("global_api_budget", self.global_api_budget.is_some()),
+            (
+                "reply_overflow",
+                self.reply_overflow == ReplyOverflow::Split,
+            ),
+            ("channel", !self.channels.is_empty()),
+        ];
+
+        features
+            .into_iter()
+            .filter_map(|(name, enabled)| enabled.then_some(name))
+            .collect()
+    }
+
+    /// A short description of what this config does, for checking at a glance
+    /// that it loaded as intended.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} passes:", self.passes.len());
+        for pass in &self.passes {
+            let stem = match pass.kind.stem() {
+                Some(Stem::Single(stem)) => stem.clone(),
+                Some(Stem::ByHost { default, hosts }) => {
+                    format!("{default} (+{} hosts)", hosts.len())
+                }
+                None => "(substitution)".to_owned(),
+            };
+            let _ = write!(summary, "\n  {} -> {stem}", pass.label);
+        }
+
+        let features = self.enabled_features();
+        let features = if features.is_empty() {
+            "none".to_owned()
+        } else {
+            features.join(", ")
+        };
+        let _ = write!(
+            summary,
+            "\nReply cache size: {}\nIgnored users: {}\nFeatures: {features}",
+            self.reply_cache_size,
+            self.ignored_users.len(),
+        );
+
+        summary
+    }
+
+    /// Parses a config file, then expands environment variables in the token
+    /// and stems and trims whitespace around labels and stems. See
+    /// [expand_vars] for the syntax.
+    pub fn from_toml(source: &str) -> Result<Self, anyhow::Error> {
+        Self::finish(toml::from_str(source)?)
+    }
+
+    /// Like [from_toml], but for a config split across several files, which
+    /// are merged in order before being parsed. See [merge_toml] for how.
+    ///
+    /// [from_toml]: Config::from_toml
+    pub fn from_tomls(sources: &[&str]) -> Result<Self, anyhow::Error> {
+        let mut merged = toml::Table::new();
+        for source in sources {
+            merge_toml(&mut merged, source.parse()?);
+        }
+
+        Self::finish(merged.try_into()?)
+    }
+
+    /// Everything [from_toml] does after parsing.
+    ///
+    /// [from_toml]: Config::from_toml
+    fn finish(mut config: Self) -> Result<Self, anyhow::Error> {
+        let env = |name: &str| std::env::var(name).ok();
+
+        config.token = expand_vars(&config.token, env)?;
+        for pass in &mut config.passes {
+            trim_value(&mut pass.label, "label");
+            for stem in pass.kind.stem_mut().into_iter().flat_map(Stem::stems_mut) {
+                *stem = expand_vars(stem, env)?;
+                trim_value(stem, "stem");
+            }
+            for route in &mut pass.routes {
+                route.stem = expand_vars(&route.stem, env)?;
+                trim_value(&mut route.stem, "stem");
+            }
+        }
+
+        // Labels are easy to misspell, and a wrong one silently does nothing
+        for channel in &config.channels {
+            let labels = channel.enabled_passes.iter().flatten();
+            for label in labels.chain(&channel.disabled_passes) {
+                if !config.passes.iter().any(|pass| pass.label == *label) {
+                    tracing::warn!("Channel {} lists unknown pass {label:?}", channel.id);
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Merges the config file `from` into `into`, overriding it. Tables like
+/// `[allowed_mentions]` are merged key by key, lists of tables like `[[pass]]`
+/// and `[[channel]]` are concatenated, and any other value replaces the one in
+/// `into`.
+fn merge_toml(into: &mut toml::Table, from: toml::Table) {
+    use toml::Value;
+
+    let tables = |array: &[Value]| array.iter().all(Value::is_table);
+    for (key, value) in from {
+        let value = match (into.remove(&key), value) {
+            (Some(Value::Table(mut old)), Value::Table(new)) => {
+                merge_toml(&mut old, new);
+                Value::Table(old)
+            }
+            (Some(Value::Array(mut old)), Value::Array(new)) if tables(&old) && tables(&new) => {
+                old.extend(new);
+                Value::Array(old)
+            }
+            (_, value) => value,
+        };
+        into.insert(key, value);
+    }
+}
+
+/// Trims whitespace from around a config value, warning if there was any since
+/// it's almost certainly a mistake.
+fn trim_value(value: &mut String, name: &str) {
+    let trimmed = value.trim();
+    if trimmed.len() != value.len() {
+        tracing::warn!("Trimmed whitespace around {name} {value:?}");
+        *value = trimmed.to_owned();
+    }
+}
+
+/// The directive that turns the bot off in a channel when it's anywhere in the
+/// channel's topic, with `topic_directives`.
+pub const TOPIC_OFF: &str = "[tweetboat:off]";
+
+/// Whether a channel topic turns the bot off. Case doesn't matter, so
+/// `[TweetBoat:Off]` works too.
+pub fn topic_disables(topic: &str) -> bool {
+    topic.to_ascii_lowercase().contains(TOPIC_OFF)
+}
+
+/// Replaces each `${NAME}` in `value` with the variable `lookup` gives for it,
+/// failing if there isn't one. `$$` is an escaped `$`, and any other `$` is
+/// left alone.
+pub fn expand_vars(
+    value: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, anyhow::Error> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let Some((name, after)) = after.split_once('}') else {
+                anyhow::bail!("Unterminated variable in {value:?}");
+            };
+
+            let Some(var) = lookup(name) else {
+                anyhow::bail!("Environment variable {name:?} is not set");
+            };
+
+            out.push_str(&var);
+            rest = after;
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Deserializes a list of `snake_case` flag names into the [MessageFlags] they
+/// combine to, failing on names that aren't flags.
+fn message_flags<'de, D: Deserializer<'de>>(de: D) -> Result<MessageFlags, D::Error> {
+    let names = Vec::<String>::deserialize(de)?;
+
+    names.iter().try_fold(MessageFlags::empty(), |flags, name| {
+        let flag = MessageFlags::from_name(&name.to_ascii_uppercase())
+            .filter(|_| name.bytes().all(|b| b.is_ascii_lowercase() || b == b'_'));
+        let Some(flag) = flag else {
+            return Err(serde::de::Error::custom(format!(
+                "unknown message flag {name:?}"
+            )));
+        };
+
+        Ok(flags | flag)
+    })
+}
+
+/// Deserializes the `[allowed_mentions]` section into the [AllowedMentions]
+/// sent with every reply. Anything not enabled is suppressed.
+fn allowed_mentions<'de, D: Deserializer<'de>>(de: D) -> Result<AllowedMentions, D::Error> {
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    struct Section {
+        users: bool,
+        roles: bool,
+        everyone: bool,
+        replied_user: bool,
+    }
+
+    let section = Section::deserialize(de)?;
+    let parse = [
+        (section.users, MentionType::Users),
+        (section.roles, MentionType::Roles),
+        (section.everyone, MentionType::Everyone),
+    ];
+
+    Ok(AllowedMentions {
+        parse: parse
+            .into_iter()
+            .filter_map(|(enabled, kind)| enabled.then_some(kind))
+            .collect(),
+        replied_user: section.replied_user,
+        ..AllowedMentions::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::channel::message::{AllowedMentions, MentionType, MessageFlags};
+    use twilight_model::gateway::Intents;
+    use twilight_model::id::Id;
+
+    use crate::pass::{ApplyOptions, Rewrite, RewriteMode};
+
+    use super::{expand_vars, topic_disables, Config};
+
+    /// Loads a config with no passes, with `extra` appended to it.
+    fn load(extra: &str) -> Config {
+        toml::from_str(&["token = \"\"\nreply_cache_size = 1\npass = []\n", extra].concat())
+            .unwrap()
+    }
+
+    #[test]
+    fn merged_files() {
+        let base = "token = \"base\"\nreply_cache_size = 1\nignored_users = [\"1\"]\n\n[allowed_mentions]\nusers = true\n\n[[pass]]\nlabel = \"Tweet\"\nregex = \"https://x\\\\.com\"\nstem = \"https://vxtwitter.com\"";
+        let secrets = "token = \"secret\"\nignored_users = []\n\n[allowed_mentions]\nroles = true";
+        let passes = "[[pass]]\nlabel = \"TikTok\"\nregex = \"https://tiktok\\\\.com\"\nstem = \"https://tiktxk.com\"";
+
+        let config = Config::from_tomls(&[base, secrets, passes]).unwrap();
+        assert_eq!(config.token, "secret");
+        assert_eq!(config.reply_cache_size, 1);
+        assert!(config.ignored_users.is_empty());
+        assert!(config.allowed_mentions.parse.contains(&MentionType::Users));
+        assert!(config.allowed_mentions.parse.contains(&MentionType::Roles));
+
+        let labels: Vec<_> = config.passes.iter().map(|pass| &pass.label).collect();
+        assert_eq!(labels, ["Tweet", "TikTok"]);
+
+        // Later files win
+        let config = Config::from_tomls(&[secrets, base]).unwrap();
+        assert_eq!(config.token, "base");
+        assert_eq!(config.ignored_users, [Id::new(1)]);
+    }
+
+    #[test]
+    fn allowed_mentions() {
+        assert_eq!(load("").allowed_mentions, AllowedMentions::default());
+
+        let config = load(
+            "
+            [allowed_mentions]
+            users = true
+            replied_user = true
+            ",
+        );
+
+        assert_eq!(
+            config.allowed_mentions,
+            AllowedMentions {
+                parse: vec![MentionType::Users],
+                replied_user: true,
+                ..AllowedMentions::default()
+            }
+        );
+    }
+
+    #[test]
+    fn expand_env_vars() {
+        let env = |name: &str| (name == "MIRROR").then(|| "fx.example".to_owned());
+
+        assert_eq!(
+            expand_vars("https://${MIRROR}/a", env).unwrap(),
+            "https://fx.example/a"
+        );
+        assert_eq!(expand_vars("$$5 or $5", env).unwrap(), "$5 or $5");
+        assert_eq!(expand_vars("$${MIRROR}", env).unwrap(), "${MIRROR}");
+
+        let missing = expand_vars("https://${UNSET}", env).unwrap_err();
+        assert!(missing.to_string().contains("UNSET"));
+        assert!(expand_vars("https://${MIRROR", env).is_err());
+    }
+
+    #[test]
+    fn trim_whitespace() {
+        let config = Config::from_toml(
+            r#"
+            token = ""
+            reply_cache_size = 1
+
+            [[pass]]
+            label = " Tweet "
+            regex = " https://x\\.com "
+            stem = "https://vxtwitter.com "
+
+            [[pass.route]]
+            path = "^/i/spaces/"
+            stem = "\thttps://fixupx.com"
+            "#,
+        )
+        .unwrap();
+
+        let pass = &config.passes[0];
+        assert_eq!(pass.label, "Tweet");
+        assert_eq!(pass.routes[0].stem, "https://fixupx.com");
+        assert_eq!(
+            pass.apply("https://x.com/a/status/1", ApplyOptions::default())
+                .as_deref(),
+            Some("[`Tweet`](https://vxtwitter.com/a/status/1)")
+        );
+    }
+
+    #[test]
+    fn apply_pass() {
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        let link = "https://x.com/a/status/1 https://www.instagram.com/p/b/";
+
+        assert_eq!(
+            config.apply_pass("Tweet", link),
+            Some(Rewrite::Fixed(
+                "[`Tweet`](https://vxtwitter.com/a/status/1)".to_owned()
+            ))
+        );
+        assert_eq!(config.apply_pass("TikTok", link), Some(Rewrite::NoLinks));
+        assert_eq!(config.apply_pass("Toot", link), None);
+    }
+
+    #[test]
+    fn passes_in() {
+        let channels = r#"
+            [[channel]]
+            id = 1
+            enabled_passes = ["Tweet", "TikTok"]
+            disabled_passes = ["TikTok"]
+
+            [[channel]]
+            id = 2
+            disabled_passes = ["Tweet"]
+        "#;
+        let config: Config =
+            toml::from_str(&[include_str!("../config.example.toml"), channels].concat()).unwrap();
+
+        let labels = |channel| {
+            config
+                .passes_in(Id::new(channel))
+                .iter()
+                .map(|pass| pass.label.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(1), ["Tweet"]);
+        assert_eq!(labels(2), ["Instagram Post", "TikTok"]);
+        assert_eq!(labels(3), ["Tweet", "Instagram Post", "TikTok"]);
+    }
+
+    #[test]
+    fn channel_reply_settings() {
+        let channels = r#"
+            [[channel]]
+            id = 1
+            rewrite_mode = "first_only"
+
+            [[channel]]
+            id = 2
+            reply_in_new_thread = true
+        "#;
+        let config: Config =
+            toml::from_str(&[include_str!("../config.example.toml"), channels].concat()).unwrap();
+
+        let mode = |channel| config.apply_options_in(Id::new(channel)).rewrite_mode;
+        assert_eq!(mode(1), RewriteMode::FirstOnly);
+        assert_eq!(mode(2), RewriteMode::All);
+        assert_eq!(mode(3), RewriteMode::All);
+
+        let threaded = |channel| config.reply_in_new_thread_in(Id::new(channel));
+        assert!(!threaded(1));
+        assert!(threaded(2));
+        assert!(!threaded(3));
+    }
+
+    #[test]
+    fn summary() {
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        assert_eq!(
+            config.summary(),
+            "3 passes:
+  Tweet -> https://vxtwitter.com
+  Instagram Post -> https://ddinstagram.com
+  TikTok -> https://tiktxk.com
+Reply cache size: 3
+Ignored users: 0
+Features: none"
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+            token = ""
+            reply_cache_size = 1
+            scan_embeds = true
+            mirror_channel = 5
+            reply_overflow = "split"
+
+            [[pass]]
+            label = "Tweet"
+            regex = "https://x\\.com"
+            stem = { "x.com" = "https://fixvx.com", default = "https://vxtwitter.com" }
+            "#,
+        )
+        .unwrap();
+        let summary = config.summary();
+        assert!(summary.contains("Tweet -> https://vxtwitter.com (+1 hosts)"));
+        assert!(summary.ends_with("Features: scan_embeds, mirror_channel, reply_overflow"));
+    }
+
+    #[test]
+    fn intents() {
+        assert_eq!(
+            load("").intents(),
+            Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT
+        );
+        assert_eq!(
+            load("topic_directives = true").intents(),
+            Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT | Intents::GUILDS
+        );
+        assert_eq!(
+            load("history_preload = 50").intents(),
+            Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT | Intents::GUILDS
+        );
+    }
+
+    #[test]
+    fn topic_directives() {
+        assert!(topic_disables("[tweetboat:off]"));
+        assert!(topic_disables("Memes only! [TweetBoat:Off] no bots"));
+        assert!(!topic_disables(""));
+        assert!(!topic_disables("tweetboat:off"));
+        assert!(!topic_disables("[tweetboat:on]"));
+        assert!(!topic_disables("[tweetboat: off]"));
+    }
+
+    #[test]
+    fn suppress_flags() {
+        assert_eq!(load("").suppress_flags, MessageFlags::SUPPRESS_EMBEDS);
+
+        let config = load("suppress_flags = [\"suppress_embeds\", \"suppress_notifications\"]");
+        assert_eq!(
+            config.suppress_flags,
+            MessageFlags::SUPPRESS_EMBEDS | MessageFlags::SUPPRESS_NOTIFICATIONS
+        );
+
+        let load = |extra: &str| {
+            let source = ["token = \"\"\nreply_cache_size = 1\npass = []\n", extra].concat();
+            toml::from_str::<Config>(&source)
+        };
+        assert!(load("suppress_flags = [\"suppress_everything\"]").is_err());
+        assert!(load("suppress_flags = [\"SUPPRESS_EMBEDS\"]").is_err());
+    }
+}
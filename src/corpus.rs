@@ -0,0 +1,89 @@
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::pass::Pass;
+
+/// Sample messages and what the bot should reply to them, for checking a
+/// config with `tweetboat check <corpus-file>`. In TOML, each case is a
+/// `[[case]]` table.
+#[derive(Deserialize)]
+pub struct Corpus {
+    #[serde(rename = "case")]
+    pub cases: Vec<Case>,
+}
+
+#[derive(Deserialize)]
+pub struct Case {
+    pub input: String,
+    /// The expected reply, or none if the bot shouldn't reply at all.
+    pub expected: Option<String>,
+}
+
+/// A case the config didn't rewrite as expected.
+pub struct Mismatch<'a> {
+    pub case: &'a Case,
+    pub actual: Option<String>,
+}
+
+impl Corpus {
+    /// Runs every case through the config's passes, returning the ones that
+    /// didn't come out as expected.
+    pub fn check(&self, config: &Config) -> Vec<Mismatch<'_>> {
+        self.cases
+            .iter()
+            .filter_map(|case| {
+                let rewrite = Pass::apply_all(&config.passes, &case.input, config.apply_options());
+                let actual = rewrite.fixed();
+                (actual != case.expected.as_deref()).then(|| Mismatch {
+                    case,
+                    actual: actual.map(str::to_owned),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Corpus;
+    use crate::config::Config;
+
+    #[test]
+    fn check() {
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        let corpus: Corpus = toml::from_str(
+            r#"
+            [[case]]
+            input = "https://x.com/a/status/1?s=46"
+            expected = "[`Tweet`](https://vxtwitter.com/a/status/1)"
+
+            [[case]]
+            input = "no links here"
+
+            [[case]]
+            input = "https://x.com/b/status/2"
+            expected = "[`Tweet`](https://fxtwitter.com/b/status/2)"
+
+            [[case]]
+            input = "`https://x.com/c/status/3`"
+            expected = "[`Tweet`](https://vxtwitter.com/c/status/3)"
+            "#,
+        )
+        .unwrap();
+
+        let mismatches = corpus.check(&config);
+        let inputs: Vec<_> = mismatches
+            .iter()
+            .map(|mismatch| mismatch.case.input.as_str())
+            .collect();
+        assert_eq!(
+            inputs,
+            ["https://x.com/b/status/2", "`https://x.com/c/status/3`"]
+        );
+        assert_eq!(
+            mismatches[0].actual.as_deref(),
+            Some("[`Tweet`](https://vxtwitter.com/b/status/2)")
+        );
+        assert_eq!(mismatches[1].actual, None);
+    }
+}
@@ -0,0 +1,72 @@
+/// Compares two texts word by word, like `git diff --word-diff=plain`: words
+/// only in `old` are wrapped in `[-…-]`, words only in `new` in `{+…+}`, and
+/// words in both are left as they are. Whitespace is collapsed to single
+/// spaces. Meant for logs, so it's a plain longest common subsequence over
+/// the words, without any of the heuristics a real diff tool has.
+pub fn word_diff(old: &str, new: &str) -> String {
+    let old: Vec<_> = old.split_whitespace().collect();
+    let new: Vec<_> = new.split_whitespace().collect();
+
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            flush(&mut removed, &mut added, &mut out);
+            out.push(old[i].to_owned());
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            removed.push(old[i]);
+            i += 1;
+        } else {
+            added.push(new[j]);
+            j += 1;
+        }
+    }
+    flush(&mut removed, &mut added, &mut out);
+
+    out.join(" ")
+}
+
+/// Writes out a run of changed words, removals first.
+fn flush<'a>(removed: &mut Vec<&'a str>, added: &mut Vec<&'a str>, out: &mut Vec<String>) {
+    if !removed.is_empty() {
+        out.push(format!("[-{}-]", removed.join(" ")));
+        removed.clear();
+    }
+    if !added.is_empty() {
+        out.push(format!("{{+{}+}}", added.join(" ")));
+        added.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::word_diff;
+
+    #[test]
+    fn simple_change() {
+        assert_eq!(
+            word_diff("look at https://x.com/a/status/1 lol", "look at [`Tweet`](https://vxtwitter.com/a/status/1) lol"),
+            "look at [-https://x.com/a/status/1-] {+[`Tweet`](https://vxtwitter.com/a/status/1)+} lol"
+        );
+        assert_eq!(word_diff("a b c", "a b c"), "a b c");
+        assert_eq!(word_diff("a b", "a b c d"), "a b {+c d+}");
+        assert_eq!(word_diff("a b c", "c"), "[-a b-] c");
+        assert_eq!(word_diff("", ""), "");
+    }
+}
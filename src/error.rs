@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use twilight_http::api_error::ApiError;
+use twilight_http::error::{Error, ErrorType};
+
+/// A rough category for a failed Discord API request, used to decide how loudly
+/// to log it and whether it's worth retrying.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorClass {
+    /// Discord rejected the request with a `429`.
+    RateLimited,
+    /// The bot is missing permissions for the channel (`403`).
+    Forbidden,
+    /// The target was deleted, usually by a user racing the bot (`404`).
+    NotFound,
+    /// A server error or a network hiccup that may succeed if tried again.
+    Transient,
+    /// Anything else, likely a bug.
+    Other,
+}
+
+impl ErrorClass {
+    pub fn of(error: &Error) -> Self {
+        match error.kind() {
+            ErrorType::Response { status, .. } => Self::from_status(status.get()),
+            ErrorType::ServiceUnavailable { .. }
+            | ErrorType::RequestError
+            | ErrorType::RequestTimedOut
+            | ErrorType::ChunkingResponse => Self::Transient,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            429 => Self::RateLimited,
+            403 => Self::Forbidden,
+            404 => Self::NotFound,
+            500..=599 => Self::Transient,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// How long Discord asked to wait before trying again, if `error` is a rate
+/// limit.
+pub fn retry_after(error: &Error) -> Option<Duration> {
+    match error.kind() {
+        ErrorType::Response {
+            error: ApiError::Ratelimited(ratelimited),
+            ..
+        } => Duration::try_from_secs_f64(ratelimited.retry_after).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorClass;
+
+    #[test]
+    fn from_status() {
+        assert_eq!(ErrorClass::from_status(429), ErrorClass::RateLimited);
+        assert_eq!(ErrorClass::from_status(403), ErrorClass::Forbidden);
+        assert_eq!(ErrorClass::from_status(404), ErrorClass::NotFound);
+        assert_eq!(ErrorClass::from_status(502), ErrorClass::Transient);
+        assert_eq!(ErrorClass::from_status(400), ErrorClass::Other);
+    }
+}
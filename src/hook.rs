@@ -0,0 +1,38 @@
+use twilight_model::id::{marker::MessageMarker, Id};
+
+/// A link the bot is replying with a fix for, as given to a [ShareHook].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SharedLink<'a> {
+    /// The message the link was posted in.
+    pub source_id: Id<MessageMarker>,
+    /// The label of the pass that matched the link.
+    pub pass_label: &'a str,
+    /// The path of the link, as in [ExtractedLink::path].
+    ///
+    /// [ExtractedLink::path]: crate::pass::ExtractedLink::path
+    pub path: &'a str,
+    /// The ID of the post the link is to, as in [ExtractedLink::status_id].
+    ///
+    /// [ExtractedLink::status_id]: crate::pass::ExtractedLink::status_id
+    pub status_id: Option<&'a str>,
+}
+
+/// Called with every link the bot fixes, so what's being shared can be
+/// recorded without parsing the logs. Any closure taking a [SharedLink] is a
+/// hook, e.g. one sending them down a channel.
+pub trait ShareHook: Send + Sync {
+    fn shared(&self, link: SharedLink<'_>);
+}
+
+impl<F: Fn(SharedLink<'_>) + Send + Sync> ShareHook for F {
+    fn shared(&self, link: SharedLink<'_>) {
+        self(link);
+    }
+}
+
+/// The hook used when none is set, which does nothing.
+pub struct NoHook;
+
+impl ShareHook for NoHook {
+    fn shared(&self, _: SharedLink<'_>) {}
+}
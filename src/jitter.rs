@@ -0,0 +1,59 @@
+/// A small deterministic random number generator (SplitMix64) for spreading
+/// out delays, so tasks started together don't all wake up at once. It's
+/// seeded explicitly so tests can predict it, and isn't suitable for anything
+/// that needs real randomness.
+#[derive(Clone, Debug)]
+pub struct Jitter {
+    state: u64,
+}
+
+impl Jitter {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Moves `delay` by a random amount of up to `jitter` in either direction,
+    /// without going below zero. A `jitter` of 0 leaves it alone.
+    pub fn apply(&mut self, delay: u64, jitter: u64) -> u64 {
+        if jitter == 0 {
+            return delay;
+        }
+
+        let offset = self.next_u64() % jitter.saturating_mul(2).saturating_add(1);
+        delay.saturating_add(offset).saturating_sub(jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Jitter;
+
+    #[test]
+    fn within_bounds() {
+        let mut jitter = Jitter::new(7);
+        assert_eq!(jitter.apply(200, 0), 200);
+
+        let delays: Vec<_> = (0..1000).map(|_| jitter.apply(200, 50)).collect();
+        assert!(delays.iter().all(|delay| (150..=250).contains(delay)));
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+
+        // Never goes negative
+        assert!((0..1000).all(|_| jitter.apply(10, 50) <= 60));
+
+        // The same seed gives the same delays
+        let mut again = Jitter::new(7);
+        again.apply(200, 0);
+        assert_eq!(
+            (0..1000).map(|_| again.apply(200, 50)).collect::<Vec<_>>(),
+            delays
+        );
+    }
+}
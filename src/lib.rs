@@ -1,4 +1,12 @@
-/// Library crate for exporting items to integration tests
+/// Library crate shared by the bot binary and the integration tests
+pub mod budget;
 pub mod cache;
 pub mod config;
+pub mod corpus;
+pub mod diff;
+pub mod error;
+pub mod hook;
+pub mod jitter;
 pub mod pass;
+pub mod quiet;
+pub mod recent;
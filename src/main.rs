@@ -1,181 +1,3839 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::future::IntoFuture;
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::future::{Future, IntoFuture};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use twilight_gateway::{Event, EventTypeFlags, Intents, Shard, ShardId, StreamExt as _};
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::sync::{oneshot, Semaphore};
+use tracing::Instrument as _;
+use twilight_gateway::{Event, EventTypeFlags, Shard, ShardId, StreamExt as _};
+use twilight_http::request::channel::reaction::RequestReactionType;
 use twilight_http::Client;
-use twilight_model::channel::message::{AllowedMentions, MessageFlags};
+use twilight_model::application::interaction::{Interaction, InteractionData};
+use twilight_model::channel::message::component::{ActionRow, Button, ButtonStyle};
+use twilight_model::channel::message::{Component, Embed, MessageFlags};
+use twilight_model::channel::{Channel, ChannelType, Message};
+use twilight_model::gateway::CloseCode;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
 use twilight_model::id::{
-    marker::{ChannelMarker, MessageMarker},
+    marker::{ChannelMarker, GuildMarker, MessageMarker, UserMarker},
     Id,
 };
+use twilight_model::util::Timestamp;
 
-use crate::cache::CacheEntry;
-use crate::pass::Pass;
-use crate::{cache::ReplyCache, config::Config};
+use tweetboat::budget::{ApiBudget, ApiPriority};
+use tweetboat::cache::{CacheEntry, ReplyCache};
+use tweetboat::config::{
+    topic_disables, Config, EmptyRewrite, ReplyOverflow, SuppressFailure, PRIVILEGED_INTENTS,
+};
+use tweetboat::corpus::{Corpus, Mismatch};
+use tweetboat::diff::word_diff;
+use tweetboat::error::{self, ErrorClass};
+use tweetboat::hook::{NoHook, ShareHook, SharedLink};
+use tweetboat::jitter::Jitter;
+use tweetboat::pass::{ApplyOptions, Pass, Rewrite};
+use tweetboat::recent::RecentFixes;
 
-mod cache;
-mod config;
-mod pass;
+#[cfg(test)]
+mod mock;
 
 struct State {
     config: Config,
     rest: Client,
     replies: RwLock<ReplyCache>,
+    suppress_permits: Arc<Semaphore>,
+    /// Channels we've already been forbidden from, to avoid repeating the warning.
+    forbidden_channels: Mutex<HashSet<Id<ChannelMarker>>>,
+    /// Replies waiting out `post_delay_millis`, removed to cancel them.
+    delayed_posts: Mutex<HashMap<Id<MessageMarker>, oneshot::Sender<()>>>,
+    /// Replies waiting out `reply_ttl_secs` or `edit_out_grace_millis` by source
+    /// message, removed to keep them.
+    reply_deletions: Mutex<HashMap<Id<MessageMarker>, oneshot::Sender<()>>>,
+    /// Spreads out suppressions by `suppress_jitter_millis`.
+    suppress_jitter: Mutex<Jitter>,
+    /// Links fixed within `dedup_window_secs`, to avoid fixing them again.
+    recent_fixes: Mutex<RecentFixes>,
+    /// Channels whose topic turns the bot off, with `topic_directives`.
+    disabled_channels: RwLock<HashSet<Id<ChannelMarker>>>,
+    /// The bot's own user ID, known once the gateway is ready.
+    user_id: OnceLock<Id<UserMarker>>,
+    /// The latest event still being handled for each message, which closes its
+    /// receiver when it's done so the next event for the message can go.
+    message_turns: Mutex<HashMap<Id<MessageMarker>, oneshot::Receiver<()>>>,
+    /// Reply edits waiting out `edit_coalesce_millis` by source message, with
+    /// the latest content. Removed to cancel them.
+    pending_edits: Mutex<HashMap<Id<MessageMarker>, Vec<String>>>,
+    /// API calls left under `global_api_budget`, if it's set.
+    api_budget: Option<Mutex<ApiBudget>>,
+    /// Told about every link the bot fixes.
+    share_hook: Box<dyn ShareHook>,
+    /// Whether messages are being left alone for maintenance, which owners can
+    /// toggle. Starts as `maintenance`.
+    paused: AtomicBool,
+    /// The current `suppress_delay_millis`, which owners can change.
+    suppress_delay_millis: AtomicU64,
+}
+
+impl State {
+    /// Starts building a state around `config`.
+    fn builder(config: Config) -> StateBuilder {
+        StateBuilder {
+            config,
+            rest: None,
+            reply_cache_size: None,
+            jitter_seed: None,
+            share_hook: None,
+        }
+    }
+
+    /// How long to wait before suppressing embeds, with jitter applied.
+    fn suppress_delay(&self) -> u64 {
+        self.suppress_jitter.lock().unwrap().apply(
+            self.suppress_delay_millis.load(Ordering::Relaxed),
+            self.config.suppress_jitter_millis,
+        )
+    }
+
+    /// Takes `calls` API calls out of `global_api_budget`, returning whether
+    /// they can be made. Logs a warning if they can't.
+    fn spend_budget(&self, calls: usize, priority: ApiPriority) -> bool {
+        let Some(budget) = &self.api_budget else {
+            return true;
+        };
+
+        let calls = u32::try_from(calls).unwrap_or(u32::MAX);
+        if budget
+            .lock()
+            .unwrap()
+            .try_spend(calls, priority, Instant::now())
+        {
+            return true;
+        }
+
+        tracing::warn!("Over the API budget, dropping {priority:?} priority calls ({calls})");
+        false
+    }
+}
+
+/// Builds a [State] from a [Config]. Anything not set explicitly comes from the
+/// config, or is fresh for a new state.
+struct StateBuilder {
+    config: Config,
+    rest: Option<Client>,
+    reply_cache_size: Option<usize>,
+    jitter_seed: Option<u64>,
+    share_hook: Option<Box<dyn ShareHook>>,
+}
+
+// The overrides are only used by tests so far
+#[cfg_attr(not(test), allow(dead_code))]
+impl StateBuilder {
+    /// Uses `rest` instead of a client made from the config's token.
+    fn rest(mut self, rest: Client) -> Self {
+        self.rest = Some(rest);
+        self
+    }
+
+    /// Overrides the config's `reply_cache_size`.
+    fn reply_cache_size(mut self, size: usize) -> Self {
+        self.reply_cache_size = Some(size);
+        self
+    }
+
+    /// Seeds the suppression jitter, which is otherwise seeded from the clock.
+    fn jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter_seed = Some(seed);
+        self
+    }
+
+    /// Tells `hook` about every link the bot fixes, instead of no one.
+    fn share_hook(mut self, hook: impl ShareHook + 'static) -> Self {
+        self.share_hook = Some(Box::new(hook));
+        self
+    }
+
+    fn build(self) -> Arc<State> {
+        let config = self.config;
+        let rest = self
+            .rest
+            .unwrap_or_else(|| Client::new(config.token.clone()));
+        let reply_cache_size = self.reply_cache_size.unwrap_or(config.reply_cache_size);
+        let seed = self.jitter_seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64)
+        });
+
+        let dedup_window = Duration::from_secs(config.dedup_window_secs.unwrap_or_default());
+
+        Arc::new(State {
+            replies: RwLock::new(ReplyCache::with_capacity(reply_cache_size)),
+            suppress_permits: Arc::new(Semaphore::new(config.suppress_concurrency)),
+            forbidden_channels: Mutex::default(),
+            delayed_posts: Mutex::default(),
+            reply_deletions: Mutex::default(),
+            suppress_jitter: Mutex::new(Jitter::new(seed)),
+            recent_fixes: Mutex::new(RecentFixes::new(dedup_window)),
+            disabled_channels: RwLock::default(),
+            user_id: OnceLock::new(),
+            message_turns: Mutex::default(),
+            pending_edits: Mutex::default(),
+            api_budget: config
+                .global_api_budget
+                .map(|per_minute| Mutex::new(ApiBudget::new(per_minute, Instant::now()))),
+            share_hook: self.share_hook.unwrap_or_else(|| Box::new(NoHook)),
+            paused: AtomicBool::new(config.maintenance),
+            suppress_delay_millis: AtomicU64::new(config.suppress_delay_millis),
+            config,
+            rest,
+        })
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     tracing_subscriber::fmt::init();
 
-    let config: Config = toml::from_str(&fs::read_to_string("config.toml")?)?;
+    let mut args: Vec<_> = std::env::args().skip(1).collect();
+    let sources = config_paths(&mut args)?
+        .iter()
+        .map(fs::read_to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    let sources: Vec<_> = sources.iter().map(String::as_str).collect();
+    let config = Config::from_tomls(&sources)?;
+    for pass in &config.passes {
+        for sample in pass.false_positives() {
+            tracing::warn!(
+                "Pass {:?} matches {sample:?}, its regex may be too broad",
+                pass.label
+            );
+        }
+    }
+
+    match &args[..] {
+        [] => {}
+        [mode, path] if mode == "check" => return check_corpus(&config, path),
+        _ => anyhow::bail!("Usage: tweetboat [--config <path>]... [check <corpus-file>]"),
+    }
 
-    let rest = Client::new(config.token.clone());
-    let shard = Shard::new(
-        ShardId::ONE,
-        config.token.clone(),
-        Intents::GUILD_MESSAGES | Intents::MESSAGE_CONTENT,
+    tracing::info!(
+        passes = config.passes.len(),
+        reply_cache_size = config.reply_cache_size,
+        ignored_users = config.ignored_users.len(),
+        "Loaded config with {}",
+        config.summary()
     );
 
-    let state = Arc::new(State {
-        replies: RwLock::new(ReplyCache::with_capacity(config.reply_cache_size)),
-        config,
-        rest,
-    });
+    let intents = config.intents();
+    tracing::info!("Connecting with intents {intents:?}");
+    let shard = Shard::new(ShardId::ONE, config.token.clone(), intents);
+
+    if config.maintenance {
+        tracing::warn!("Starting in maintenance, leaving messages alone until {RESUME_COMMAND:?}");
+    }
 
+    let state = State::builder(config).build();
     shard_loop(state, shard).await
 }
 
+/// Takes every `--config <path>` out of `args`, giving the config files to
+/// merge, in order. A directory stands for the `.toml` files in it, sorted by
+/// name. Without any, it's just `config.toml`.
+fn config_paths(args: &mut Vec<String>) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut paths = Vec::new();
+    while let Some(idx) = args.iter().position(|arg| arg == "--config") {
+        let Some(path) = args.get(idx + 1) else {
+            anyhow::bail!("--config needs a path");
+        };
+        let path = PathBuf::from(path);
+        args.drain(idx..idx + 2);
+
+        if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(&path)? {
+                let file = entry?.path();
+                if file.extension().is_some_and(|ext| ext == "toml") {
+                    files.push(file);
+                }
+            }
+            files.sort();
+            paths.extend(files);
+        } else {
+            paths.push(path);
+        }
+    }
+
+    if paths.is_empty() {
+        paths.push(PathBuf::from("config.toml"));
+    }
+    Ok(paths)
+}
+
+/// Runs the config over a corpus of sample messages, printing every case that
+/// wasn't rewritten as expected and failing if there were any.
+fn check_corpus(config: &Config, path: &str) -> Result<(), anyhow::Error> {
+    let corpus: Corpus = toml::from_str(&fs::read_to_string(path)?)?;
+    let mismatches = corpus.check(config);
+
+    for Mismatch { case, actual } in &mismatches {
+        println!("{:?}", case.input);
+        println!("  expected {:?}", case.expected);
+        println!("  got      {actual:?}");
+    }
+
+    let total = corpus.cases.len();
+    println!("{} of {total} cases passed", total - mismatches.len());
+    if !mismatches.is_empty() {
+        anyhow::bail!("{} cases failed", mismatches.len());
+    }
+
+    Ok(())
+}
+
 async fn shard_loop(state: Arc<State>, mut shard: Shard) -> Result<(), anyhow::Error> {
     while let Some(event) = shard.next_event(EventTypeFlags::all()).await {
-        if let Err(e) = dispatch_event(Arc::clone(&state), event?).await {
-            tracing::error!(error = ?e, "Dispatch failed");
+        let event = event?;
+        if let Event::GatewayClose(Some(frame)) = &event {
+            if frame.code == CloseCode::DisallowedIntents as u16 {
+                let privileged = state.config.intents() & PRIVILEGED_INTENTS;
+                anyhow::bail!(
+                    "Discord refused the privileged intents {privileged:?}, enable them for the bot in the developer portal"
+                );
+            }
         }
+
+        spawn_dispatch(&state, event);
     }
 
     Ok(())
 }
 
+/// Launches a background Tokio task to handle an event, so slow requests don't
+/// hold up the gateway. Events for the same message are still handled one at a
+/// time in the order they arrived, so an edit never overtakes its create.
+fn spawn_dispatch(state: &Arc<State>, event: Event) -> tokio::task::JoinHandle<()> {
+    let channel_id = event_channel(&event);
+    let span = event_span(&state.config, &event);
+
+    // Dropping the sender when done lets the next event for the message go
+    let (done, turn) = oneshot::channel::<()>();
+    let previous = event_message(&event).and_then(|message_id| {
+        let mut message_turns = state.message_turns.lock().unwrap();
+        // Forget events that are done, so only ones in progress are kept
+        message_turns.retain(|_, turn| matches!(turn.try_recv(), Err(TryRecvError::Empty)));
+        message_turns.insert(message_id, turn)
+    });
+
+    let state = Arc::clone(state);
+    let task = async move {
+        if let Some(previous) = previous {
+            // Closed either way once the previous event is done
+            let _ = previous.await;
+        }
+
+        if let Err(e) = dispatch_event(Arc::clone(&state), event).await {
+            report_error(&state, channel_id, &e);
+        }
+        if state.config.trace_events {
+            // Logged in the span, so its fields are shown as they ended up
+            tracing::debug!("Event handled");
+        }
+        drop(done);
+    };
+    tokio::spawn(task.instrument(span))
+}
+
+/// The span an event is handled in with `trace_events`, which everything
+/// logged while handling it (including twilight's requests) is tied to. The
+/// dispatch fills in `matched` and `decision` as it goes.
+fn event_span(config: &Config, event: &Event) -> tracing::Span {
+    if !config.trace_events {
+        return tracing::Span::none();
+    }
+
+    tracing::debug_span!(
+        "event",
+        kind = ?event.kind(),
+        message = event_message(event).map(Id::get),
+        channel = event_channel(event).map(Id::get),
+        matched = tracing::field::Empty,
+        decision = tracing::field::Empty,
+    )
+}
+
+/// Notes what was done with an event on its span, with `trace_events`.
+fn record_decision(decision: &'static str) {
+    tracing::Span::current().record("decision", decision);
+}
+
+/// Notes on the event's span whether its links were rewritten, with
+/// `trace_events`.
+fn record_matched(matched: bool) {
+    tracing::Span::current().record("matched", matched);
+}
+
+/// Gets the channel an event happened in, for the events we handle.
+fn event_channel(event: &Event) -> Option<Id<ChannelMarker>> {
+    match event {
+        Event::MessageCreate(message) => Some(message.channel_id),
+        Event::MessageUpdate(message) => Some(message.channel_id),
+        Event::MessageDelete(message) => Some(message.channel_id),
+        _ => None,
+    }
+}
+
+/// Gets the message an event is about, for the events we handle.
+fn event_message(event: &Event) -> Option<Id<MessageMarker>> {
+    match event {
+        Event::MessageCreate(message) => Some(message.id),
+        Event::MessageUpdate(message) => Some(message.id),
+        Event::MessageDelete(message) => Some(message.id),
+        _ => None,
+    }
+}
+
+/// Logs a failed dispatch at a level depending on its [ErrorClass]. Missing
+/// permissions are only warned about once per channel.
+fn report_error(state: &State, channel_id: Option<Id<ChannelMarker>>, error: &anyhow::Error) {
+    let class = error
+        .downcast_ref::<twilight_http::Error>()
+        .map_or(ErrorClass::Other, ErrorClass::of);
+
+    match class {
+        ErrorClass::Forbidden => {
+            let first = channel_id.is_none_or(|channel_id| {
+                state.forbidden_channels.lock().unwrap().insert(channel_id)
+            });
+
+            if first {
+                tracing::warn!(error = ?error, "Missing permissions in channel {channel_id:?}");
+            } else {
+                tracing::debug!(error = ?error, "Missing permissions in channel {channel_id:?}");
+            }
+        }
+        ErrorClass::NotFound => tracing::debug!(error = ?error, "Dispatch target was deleted"),
+        ErrorClass::RateLimited => tracing::warn!(error = ?error, "Dispatch was rate limited"),
+        ErrorClass::Transient => tracing::warn!(error = ?error, "Dispatch failed after retrying"),
+        ErrorClass::Other => tracing::error!(error = ?error, "Dispatch failed"),
+    }
+}
+
+/// The number of times a request is retried after a [transient] failure.
+///
+/// [transient]: ErrorClass::Transient
+const TRANSIENT_RETRIES: u32 = 2;
+
+/// Sends the request built by `request`, rebuilding and resending it with a
+/// short backoff if it fails for a [transient] reason.
+///
+/// [transient]: ErrorClass::Transient
+async fn retry_transient<T, F>(mut request: impl FnMut() -> F) -> Result<T, twilight_http::Error>
+where
+    F: Future<Output = Result<T, twilight_http::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Err(e)
+                if attempt < TRANSIENT_RETRIES && ErrorClass::of(&e) == ErrorClass::Transient =>
+            {
+                attempt += 1;
+                tracing::debug!(error = ?e, "Retrying request (attempt {attempt})");
+                tokio::time::sleep(Duration::from_millis(100 * u64::from(attempt))).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The longest a [rate limited] request waits before it's tried again, in case
+/// Discord asks for something unreasonable.
+///
+/// [rate limited]: ErrorClass::RateLimited
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10);
+
+/// Like [retry_transient], but also resends the request up to `retries` times
+/// if it's [rate limited], waiting as long as Discord asks first.
+///
+/// [rate limited]: ErrorClass::RateLimited
+async fn retry_rate_limited<T, F>(
+    retries: u32,
+    mut request: impl FnMut() -> F,
+) -> Result<T, twilight_http::Error>
+where
+    F: Future<Output = Result<T, twilight_http::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match retry_transient(&mut request).await {
+            Err(e) if attempt < retries && ErrorClass::of(&e) == ErrorClass::RateLimited => {
+                attempt += 1;
+                let wait = error::retry_after(&e).unwrap_or(Duration::from_secs(1));
+                tracing::debug!("Rate limited, retrying in {wait:?} (attempt {attempt})");
+                tokio::time::sleep(wait.min(MAX_RATE_LIMIT_WAIT)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
 /// Launches a background Tokio task to suppress an embed. If the request fails,
-/// the error is logged. The resulting [Joinhandle] is returned.
+/// the error is logged. The resulting [Joinhandle] is returned, which gives
+/// whether the embed was suppressed.
+///
+/// The request is only sent once a permit is available from `permits`, which
+/// bounds how many suppressions can be in flight at once.
 ///
 /// [JoinHandle]: tokio::task::JoinHandle
 fn suppress_embeds_deferred(
     rest: &Client,
+    permits: &Arc<Semaphore>,
     delay: u64,
+    flags: MessageFlags,
     channel_id: Id<ChannelMarker>,
     message_id: Id<MessageMarker>,
-) -> tokio::task::JoinHandle<()> {
+) -> tokio::task::JoinHandle<bool> {
     // Create the future separately from spawning so that `client` isn't sent across threads
     let f = rest
         .update_message(channel_id, message_id)
-        .flags(MessageFlags::SUPPRESS_EMBEDS)
+        .flags(flags)
         .into_future();
+    let permits = Arc::clone(permits);
 
     tokio::spawn(async move {
         if delay > 0 {
             tokio::time::sleep(Duration::from_millis(delay)).await;
         }
 
+        // The semaphore is never closed
+        let _permit = permits.acquire().await.unwrap();
         if let Err(e) = f.await {
             tracing::error!(error = ?e, "Error suppressing embeds on {channel_id}/{message_id}");
+            return false;
         }
+
+        true
     })
 }
 
-async fn dispatch_event(state: Arc<State>, event: Event) -> Result<(), anyhow::Error> {
-    match event {
-        // CREATE: Fix embeds when someone sends a twitter link
-        Event::MessageCreate(message) => {
-            if message.author.bot || state.config.ignored_users.contains(&message.author.id) {
-                return Ok(());
-            }
+/// The reaction left in place of a reply removed by `on_suppress_failure`.
+const SUPPRESS_FAILURE_REACTION: RequestReactionType =
+    RequestReactionType::Unicode { name: "🔗" };
 
-            if let Some(content) = Pass::apply_all(&state.config.passes, &message.content) {
-                tracing::info!("Rewriting {:?} => {content:?}", message.content);
+/// Launches a background Tokio task that waits for a suppression, and removes
+/// the reply to its message if it failed, following `on_suppress_failure`.
+/// Nothing is launched if the reply is kept either way.
+fn watch_suppression(
+    state: &Arc<State>,
+    suppress: tokio::task::JoinHandle<bool>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+) {
+    if state.config.on_suppress_failure == SuppressFailure::Keep {
+        return;
+    }
 
-                // If the unfurler has an embed cached, embeds will be included
-                if !message.embeds.is_empty() {
-                    suppress_embeds_deferred(
-                        &state.rest,
-                        state.config.suppress_delay_millis,
-                        message.channel_id,
-                        message.id,
-                    );
-                }
+    let state = Arc::clone(state);
+    tokio::spawn(async move {
+        // A suppression that panicked didn't suppress anything either
+        if suppress.await.unwrap_or(false) {
+            return;
+        }
 
-                let token = state.replies.write().unwrap().file_pending(message.id);
-                if let Some(token) = token {
-                    let reply = state
-                        .rest
-                        .create_message(message.channel_id)
-                        .content(&content)
-                        .reply(message.id)
-                        .allowed_mentions(Some(&AllowedMentions::default()))
-                        .await?
-                        .model()
-                        .await?;
+        if let Err(e) = remove_unsuppressed_reply(&state, channel_id, message_id).await {
+            report_error(&state, Some(channel_id), &e);
+        }
+    });
+}
 
-                    state.replies.write().unwrap().insert(token, reply.id);
-                }
+/// Removes the reply to a message whose embeds couldn't be suppressed: one
+/// waiting out `post_delay_millis` is cancelled, and one that was sent is
+/// deleted. With [SuppressFailure::React], the message is reacted to instead.
+async fn remove_unsuppressed_reply(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+) -> Result<(), anyhow::Error> {
+    let cancelled = state
+        .delayed_posts
+        .lock()
+        .unwrap()
+        .remove(&message_id)
+        .is_some();
+    let (reply, overflow) = {
+        let mut replies = state.replies.write().unwrap();
+        let entry = replies.get_entry(message_id);
+        // A pending entry is left alone, so the reply in flight isn't lost track of
+        match entry.and_then(|entry| entry.reply(channel_id, message_id)) {
+            Some(reply) => {
+                replies.take_entry(message_id);
+                (Some(reply), replies.take_overflow(message_id))
             }
+            None => (None, Vec::new()),
         }
+    };
+    if !cancelled && reply.is_none() {
+        return Ok(());
+    }
 
-        // UPDATE: Edit our reply when someone edits a link in/out
-        Event::MessageUpdate(message) => {
-            let entry = state.replies.read().unwrap().get_entry(message.id);
-            let Some(entry) = entry else {
-                return Ok(());
-            };
+    tracing::info!("Embeds on {message_id} couldn't be suppressed, removing the reply");
+    state.reply_deletions.lock().unwrap().remove(&message_id);
+    state.pending_edits.lock().unwrap().remove(&message_id);
+    if let Some((reply_channel_id, reply_id)) = reply {
+        retry_transient(|| {
+            state
+                .rest
+                .delete_message(reply_channel_id, reply_id)
+                .into_future()
+        })
+        .await?;
+        delete_overflow(state, reply_channel_id, &overflow).await?;
+    }
 
-            // Suppress embeds the unfurler provided lazily
-            if message.embeds.is_some_and(|embeds| !embeds.is_empty()) {
-                tracing::info!("Unfurler triggered on {:?}, suppressing...", entry);
-                suppress_embeds_deferred(
-                    &state.rest,
-                    state.config.suppress_delay_millis,
-                    message.channel_id,
-                    message.id,
-                );
-            };
+    if state.config.on_suppress_failure == SuppressFailure::React {
+        retry_transient(|| {
+            state
+                .rest
+                .create_reaction(channel_id, message_id, &SUPPRESS_FAILURE_REACTION)
+                .into_future()
+        })
+        .await?;
+    }
 
-            if let CacheEntry::Filled(reply_id) = entry {
-                if let Some(content) = message.content {
-                    if let Some(content) = Pass::apply_all(&state.config.passes, &content) {
-                        state
-                            .rest
-                            .update_message(message.channel_id, reply_id)
-                            .allowed_mentions(Some(&AllowedMentions::default()))
-                            .content(Some(&content))
-                            .await?;
-                    } else {
-                        state
-                            .rest
-                            .delete_message(message.channel_id, reply_id)
-                            .await?;
-                    }
+    Ok(())
+}
+
+/// Replies to a source message with its rewritten content, tracking the reply
+/// in the cache. With a `mirror_channel` the reply is posted there instead.
+/// The content is the [chunks] of the reply, each sent as its own message.
+///
+/// [chunks]: reply_chunks
+async fn post_reply(
+    state: &Arc<State>,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    author_id: Option<Id<UserMarker>>,
+    chunks: &[String],
+) -> Result<(), anyhow::Error> {
+    let Some((content, rest)) = chunks.split_first() else {
+        return Ok(());
+    };
+    let components = reply_components(state, message_id, author_id);
+
+    let token = state.replies.write().unwrap().file_pending(message_id);
+    if let Some(token) = token {
+        let threaded = if state.config.mirror_channel.is_none()
+            && state.config.reply_in_new_thread_in(channel_id)
+        {
+            match post_in_new_thread(state, channel_id, message_id, content, &components).await {
+                Ok(reply_id) => Some(reply_id),
+                Err(e) => {
+                    // Most likely missing the permission to create threads
+                    tracing::warn!(error = ?e, "Couldn't reply in a new thread on {channel_id}/{message_id}, replying normally");
+                    None
                 }
             }
-        }
+        } else {
+            None
+        };
 
-        // DELETE: Delete our reply when someone deletes their source message
-        Event::MessageDelete(message) => {
-            let entry = state.replies.write().unwrap().take_entry(message.id);
+        if let Some(mirror_id) = state.config.mirror_channel {
+            let content = mirrored_content(state, guild_id, channel_id, message_id, content);
+            let reply = send_message(state, || {
+                state
+                    .rest
+                    .create_message(mirror_id)
+                    .content(&content)
+                    .components(&components)
+                    .allowed_mentions(Some(&state.config.allowed_mentions))
+                    .into_future()
+            })
+            .await;
+            give_up_if_failed(state, message_id, &reply);
+            let reply = reply?;
 
-            // Temporary extension with `if let` pulls the guard across the await
-            // boundary as it keeps the temp. alive for the entire scope, so we need
-            // to separate it
-            if let Some(CacheEntry::Filled(reply_id)) = entry {
+            state
+                .replies
+                .write()
+                .unwrap()
+                .insert_mirrored(token, mirror_id, reply.id);
+        } else if let Some(reply_id) = threaded {
+            state
+                .replies
+                .write()
+                .unwrap()
+                .insert_threaded(token, reply_id);
+        } else {
+            let reply = send_message(state, || {
                 state
                     .rest
-                    .delete_message(message.channel_id, reply_id)
-                    .await?;
-            }
+                    .create_message(channel_id)
+                    .content(content)
+                    .components(&components)
+                    .reply(message_id)
+                    .allowed_mentions(Some(&state.config.allowed_mentions))
+                    .into_future()
+            })
+            .await;
+            give_up_if_failed(state, message_id, &reply);
+            let reply = reply?;
+
+            state.replies.write().unwrap().insert(token, reply.id);
         }
 
-        _ => {}
+        sync_overflow(state, channel_id, message_id, rest).await?;
+
+        if let Some(ttl) = state.config.reply_ttl_secs {
+            let ttl = Duration::from_secs(ttl);
+            delete_reply_deferred(Arc::clone(state), ttl, channel_id, message_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a message for a reply, [retrying] it if it's rate limited, and reads
+/// back what was sent.
+///
+/// [retrying]: retry_rate_limited
+async fn send_message<F>(
+    state: &State,
+    request: impl FnMut() -> F,
+) -> Result<Message, anyhow::Error>
+where
+    F: Future<Output = Result<twilight_http::Response<Message>, twilight_http::Error>>,
+{
+    let reply = retry_rate_limited(state.config.reply_rate_limit_retries, request).await?;
+    Ok(reply.model().await?)
+}
+
+/// Drops the pending entry of a source message if sending its reply failed,
+/// so it isn't left waiting forever.
+fn give_up_if_failed<T>(
+    state: &State,
+    source_id: Id<MessageMarker>,
+    reply: &Result<T, anyhow::Error>,
+) {
+    if let Err(e) = reply {
+        tracing::warn!(error = ?e, "Giving up on the reply to {source_id}");
+        let mut replies = state.replies.write().unwrap();
+        replies.retain(|source, _| source != source_id);
+    }
+}
+
+/// Edits the reply to a source message to the [chunks] of its new content,
+/// sending it again if it was deleted. Nothing happens if there's no reply.
+///
+/// [chunks]: reply_chunks
+async fn edit_reply(
+    state: &Arc<State>,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    author_id: Option<Id<UserMarker>>,
+    chunks: &[String],
+) -> Result<(), anyhow::Error> {
+    let entry = state.replies.read().unwrap().get_entry(message_id);
+    let Some(entry) = entry else {
+        return Ok(());
+    };
+    let Some((reply_channel_id, reply_id)) = entry.reply(channel_id, message_id) else {
+        return Ok(());
+    };
+
+    // The first message, and then one call for each chunk or leftover message
+    let overflow = state.replies.read().unwrap().overflow(message_id).len();
+    if !state.spend_budget(chunks.len().max(1 + overflow), ApiPriority::Primary) {
+        return Ok(());
+    }
+
+    let edited = match entry {
+        CacheEntry::Mirrored(..) => Cow::Owned(mirrored_content(
+            state, guild_id, channel_id, message_id, &chunks[0],
+        )),
+        _ => Cow::Borrowed(chunks[0].as_str()),
+    };
+
+    let update = retry_transient(|| {
+        state
+            .rest
+            .update_message(reply_channel_id, reply_id)
+            .allowed_mentions(Some(&state.config.allowed_mentions))
+            .content(Some(&edited))
+            .into_future()
+    })
+    .await;
+
+    match update {
+        // Someone deleted the reply, so send it again
+        Err(e) if ErrorClass::of(&e) == ErrorClass::NotFound => {
+            tracing::info!("Reply to {message_id} is gone, replying again");
+            let overflow = {
+                let mut replies = state.replies.write().unwrap();
+                let overflow = replies.take_overflow(message_id);
+                replies.retain(|source, _| source != message_id);
+                overflow
+            };
+
+            // Start over so the messages stay in order
+            delete_overflow(state, reply_channel_id, &overflow).await?;
+            post_reply(state, guild_id, channel_id, message_id, author_id, chunks).await?;
+        }
+        update => {
+            update?;
+            sync_overflow(state, channel_id, message_id, &chunks[1..]).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Launches a background Tokio task to [edit a reply] after `delay`
+/// milliseconds, so a burst of edits to the source makes one request. If an
+/// edit to the same reply is already waiting, it's given these chunks instead
+/// and no task is launched. The edit is cancelled if the source's entry is
+/// removed from [State::pending_edits] before then.
+///
+/// [edit a reply]: edit_reply
+fn edit_reply_deferred(
+    state: Arc<State>,
+    delay: u64,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    author_id: Option<Id<UserMarker>>,
+    chunks: Vec<String>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let mut pending_edits = state.pending_edits.lock().unwrap();
+    if pending_edits.insert(message_id, chunks).is_some() {
+        return None;
+    }
+    drop(pending_edits);
+
+    Some(tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+
+        let chunks = state.pending_edits.lock().unwrap().remove(&message_id);
+        let Some(chunks) = chunks else {
+            return;
+        };
+        let edit = edit_reply(&state, guild_id, channel_id, message_id, author_id, &chunks);
+        if let Err(e) = edit.await {
+            report_error(&state, Some(channel_id), &e);
+        }
+    }))
+}
+
+/// How often a [deferred edit] checks whether the reply it's waiting on has
+/// been sent, in milliseconds.
+///
+/// [deferred edit]: edit_when_sent
+const PENDING_RECHECK_MILLIS: u64 = 100;
+
+/// How many times a [deferred edit] checks before giving up on the reply.
+///
+/// [deferred edit]: edit_when_sent
+const PENDING_RECHECKS: u32 = 50;
+
+/// Waits for the pending entry of a source message to be filled, returning
+/// whether it was. It isn't if the reply was given up on, or is still pending
+/// after [PENDING_RECHECKS] checks, which may mean it was never sent.
+async fn wait_until_sent(state: &State, source_id: Id<MessageMarker>) -> bool {
+    for _ in 0..PENDING_RECHECKS {
+        tokio::time::sleep(Duration::from_millis(PENDING_RECHECK_MILLIS)).await;
+
+        let entry = state.replies.read().unwrap().get_entry(source_id);
+        match entry {
+            Some(CacheEntry::Pending) => continue,
+            Some(_) => return true,
+            None => return false,
+        }
+    }
+
+    tracing::debug!("Reply to {source_id} never showed up, dropping the edit");
+    false
+}
+
+/// Launches a background Tokio task to [edit the reply] to a source message
+/// whose entry is still pending, once the reply has been sent. This covers
+/// the source being edited, or unfurled, while its reply is in flight. Like
+/// [edit_reply_deferred], a waiting edit is given these chunks instead of
+/// launching another task, and removing the source from
+/// [State::pending_edits] cancels it. If the reply never shows up, nothing is
+/// edited.
+///
+/// [edit the reply]: edit_reply
+fn edit_when_sent(
+    state: Arc<State>,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    author_id: Option<Id<UserMarker>>,
+    chunks: Vec<String>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let mut pending_edits = state.pending_edits.lock().unwrap();
+    if pending_edits.insert(message_id, chunks).is_some() {
+        return None;
+    }
+    drop(pending_edits);
+
+    Some(tokio::spawn(async move {
+        let sent = wait_until_sent(&state, message_id).await;
+        let chunks = state.pending_edits.lock().unwrap().remove(&message_id);
+        let (true, Some(chunks)) = (sent, chunks) else {
+            return;
+        };
+        let edit = edit_reply(&state, guild_id, channel_id, message_id, author_id, &chunks);
+        if let Err(e) = edit.await {
+            report_error(&state, Some(channel_id), &e);
+        }
+    }))
+}
+
+/// Brings the messages after the first of a split reply to a source message in
+/// line with `rest`, editing the ones already sent, sending any more that are
+/// needed, and deleting any left over.
+async fn sync_overflow(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    source_id: Id<MessageMarker>,
+    rest: &[String],
+) -> Result<(), anyhow::Error> {
+    let entry = state.replies.read().unwrap().get_entry(source_id);
+    let reply = entry.and_then(|entry| entry.reply(channel_id, source_id));
+    let Some((reply_channel_id, _)) = reply else {
+        return Ok(());
+    };
+
+    let sent = state.replies.read().unwrap().overflow(source_id).to_vec();
+    if sent.is_empty() && rest.is_empty() {
+        return Ok(());
+    }
+
+    let mut replies = Vec::with_capacity(rest.len());
+    for (idx, chunk) in rest.iter().enumerate() {
+        let reply_id = match sent.get(idx) {
+            Some(&reply_id) => {
+                retry_transient(|| {
+                    state
+                        .rest
+                        .update_message(reply_channel_id, reply_id)
+                        .allowed_mentions(Some(&state.config.allowed_mentions))
+                        .content(Some(chunk))
+                        .into_future()
+                })
+                .await?;
+                reply_id
+            }
+            None => {
+                send_message(state, || {
+                    state
+                        .rest
+                        .create_message(reply_channel_id)
+                        .content(chunk)
+                        .allowed_mentions(Some(&state.config.allowed_mentions))
+                        .into_future()
+                })
+                .await?
+                .id
+            }
+        };
+        replies.push(reply_id);
+    }
+
+    state
+        .replies
+        .write()
+        .unwrap()
+        .set_overflow(source_id, replies);
+    let leftover = sent.get(rest.len()..).unwrap_or_default();
+    delete_overflow(state, reply_channel_id, leftover).await?;
+
+    Ok(())
+}
+
+/// Deletes the messages after the first of a split reply.
+async fn delete_overflow(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    replies: &[Id<MessageMarker>],
+) -> Result<(), twilight_http::Error> {
+    for &reply_id in replies {
+        retry_transient(|| {
+            state
+                .rest
+                .delete_message(channel_id, reply_id)
+                .into_future()
+        })
+        .await?;
     }
 
     Ok(())
 }
+
+/// The most characters Discord allows in a message.
+const MESSAGE_LIMIT: usize = 2000;
+
+/// Room left in replies to the `mirror_channel` for the jump link in front of
+/// them. The three IDs in it are at most 20 digits each.
+const JUMP_LINK_ROOM: usize = 100;
+
+/// Room left in fixes posted with `delete_original` for the mention of the
+/// author in front of them.
+const AUTHOR_MENTION_ROOM: usize = 25;
+
+/// Breaks a reply the `passes` made into the messages it's sent as. Replies
+/// that fit in one message are left whole, and the rest are split up or cut
+/// short following `reply_overflow`.
+fn reply_chunks(
+    state: &State,
+    passes: &[&Pass],
+    scanned: &str,
+    options: ApplyOptions,
+    fixed: String,
+) -> Vec<String> {
+    let limit = match state.config.mirror_channel {
+        _ if state.config.delete_original => MESSAGE_LIMIT - AUTHOR_MENTION_ROOM,
+        Some(_) if state.config.include_jump_link => MESSAGE_LIMIT - JUMP_LINK_ROOM,
+        _ => MESSAGE_LIMIT,
+    };
+
+    // Bytes are never fewer than characters, so this only errs on the short side
+    if fixed.len() <= limit {
+        return vec![fixed];
+    }
+
+    let mut chunks = Pass::apply_all_split(passes, scanned, options, limit);
+    if state.config.reply_overflow == ReplyOverflow::Truncate {
+        tracing::debug!("Reply is too long, dropping {} chunks", chunks.len() - 1);
+        chunks.truncate(1);
+    }
+
+    chunks
+}
+
+/// Prefixes a reply posted to the `mirror_channel` with a jump link back to its
+/// source message, with `include_jump_link`.
+fn mirrored_content(
+    state: &State,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    content: &str,
+) -> String {
+    match jump_url(guild_id, channel_id, message_id) {
+        Some(url) if state.config.include_jump_link => format!("{url} {content}"),
+        _ => content.to_owned(),
+    }
+}
+
+/// The link that jumps to a message in the Discord client. Messages outside a
+/// guild don't have one that works for everyone, so they get none.
+fn jump_url(
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+) -> Option<String> {
+    let guild_id = guild_id?;
+    Some(format!(
+        "https://discord.com/channels/{guild_id}/{channel_id}/{message_id}"
+    ))
+}
+
+/// What the custom ID of a reply's delete button starts with. The source
+/// message and its author follow, like `delete:1:2`.
+const DELETE_BUTTON_PREFIX: &str = "delete:";
+
+/// The buttons put on a reply with `reply_buttons`: one that deletes it, for
+/// the author of its source message. Replies whose source's author isn't
+/// known get none.
+fn reply_components(
+    state: &State,
+    message_id: Id<MessageMarker>,
+    author_id: Option<Id<UserMarker>>,
+) -> Vec<Component> {
+    let Some(author_id) = author_id.filter(|_| state.config.reply_buttons) else {
+        return Vec::new();
+    };
+
+    let delete = Button {
+        custom_id: Some(format!("{DELETE_BUTTON_PREFIX}{message_id}:{author_id}")),
+        disabled: false,
+        emoji: None,
+        label: Some("Delete".to_owned()),
+        style: ButtonStyle::Danger,
+        url: None,
+    };
+    vec![Component::ActionRow(ActionRow {
+        components: vec![Component::Button(delete)],
+    })]
+}
+
+/// Reads the source message and its author back out of the custom ID of a
+/// reply's delete button.
+fn parse_delete_button(custom_id: &str) -> Option<(Id<MessageMarker>, Id<UserMarker>)> {
+    let ids = custom_id.strip_prefix(DELETE_BUTTON_PREFIX)?;
+    let (message_id, author_id) = ids.split_once(':')?;
+    Some((message_id.parse().ok()?, author_id.parse().ok()?))
+}
+
+/// What someone other than the source's author is told when they click a
+/// reply's delete button. Only they can see it.
+const DELETE_BUTTON_REFUSAL: &str = "Only the person who posted the link can delete this.";
+
+/// Handles a click on a reply's delete button: the reply is deleted if whoever
+/// clicked it wrote the source message, and they're turned away otherwise.
+async fn delete_from_button(
+    state: &State,
+    interaction: &Interaction,
+    source_id: Id<MessageMarker>,
+    author_id: Id<UserMarker>,
+) -> Result<(), anyhow::Error> {
+    let interactions = state.rest.interaction(interaction.application_id);
+    if interaction.author_id() != Some(author_id) {
+        record_decision("not the author");
+        let response = InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseData {
+                content: Some(DELETE_BUTTON_REFUSAL.to_owned()),
+                flags: Some(MessageFlags::EPHEMERAL),
+                ..InteractionResponseData::default()
+            }),
+        };
+        interactions
+            .create_response(interaction.id, &interaction.token, &response)
+            .await?;
+        return Ok(());
+    }
+
+    // Only now, so clicks from anyone else can't use up the budget
+    let overflow = state.replies.read().unwrap().overflow(source_id).len();
+    if !state.spend_budget(2 + overflow, ApiPriority::Primary) {
+        record_decision("over budget");
+        return Ok(());
+    }
+
+    record_decision("delete");
+    // Discord only waits a few seconds for a response, so answer right away
+    let response = InteractionResponse {
+        kind: InteractionResponseType::DeferredUpdateMessage,
+        data: None,
+    };
+    interactions
+        .create_response(interaction.id, &interaction.token, &response)
+        .await?;
+
+    // Left pending, so edits to the source don't bring the reply back
+    state.reply_deletions.lock().unwrap().remove(&source_id);
+    state.pending_edits.lock().unwrap().remove(&source_id);
+    let overflow = {
+        let mut replies = state.replies.write().unwrap();
+        replies.take_entry(source_id);
+        replies.take_overflow(source_id)
+    };
+
+    let Some(reply) = &interaction.message else {
+        return Ok(());
+    };
+    retry_transient(|| {
+        state
+            .rest
+            .delete_message(reply.channel_id, reply.id)
+            .into_future()
+    })
+    .await?;
+    delete_overflow(state, reply.channel_id, &overflow).await?;
+
+    Ok(())
+}
+
+/// Replaces a source message with its fix, with `delete_original`: the [chunks]
+/// of the fix are posted, starting with a mention of its author, and then the
+/// message is deleted. Returns whether the message was deleted, which takes the
+/// manage messages permission. If it wasn't, the fix is taken down again. If
+/// the fix couldn't be posted, the message is left alone.
+///
+/// The fix isn't cached like a reply, so the delete event for the message
+/// finds nothing to clean up, and the fix stays.
+///
+/// [chunks]: reply_chunks
+async fn replace_original(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    author_id: Id<UserMarker>,
+    chunks: &[String],
+) -> Result<bool, anyhow::Error> {
+    let mut posted = Vec::with_capacity(chunks.len());
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let content = if idx == 0 {
+            format!("<@{author_id}>: {chunk}")
+        } else {
+            chunk.clone()
+        };
+        let sent = send_message(state, || {
+            state
+                .rest
+                .create_message(channel_id)
+                .content(&content)
+                .allowed_mentions(Some(&state.config.allowed_mentions))
+                .into_future()
+        })
+        .await;
+
+        match sent {
+            Ok(sent) => posted.push(sent.id),
+            Err(e) => {
+                // Don't leave part of the fix behind
+                delete_overflow(state, channel_id, &posted).await?;
+                return Err(e);
+            }
+        }
+    }
+
+    let deleted = retry_transient(|| {
+        state
+            .rest
+            .delete_message(channel_id, message_id)
+            .into_future()
+    })
+    .await;
+    if let Err(e) = deleted {
+        // Most likely missing the permission to manage messages
+        tracing::warn!(error = ?e, "Couldn't delete {channel_id}/{message_id}, replying instead");
+        delete_overflow(state, channel_id, &posted).await?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// The name given to threads created by `reply_in_new_thread`.
+const THREAD_NAME: &str = "Fixed links";
+
+/// Starts a thread from a source message and posts its rewritten content
+/// inside, returning the ID of the reply. If the reply can't be posted, the
+/// thread is deleted so an empty one isn't left behind.
+async fn post_in_new_thread(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    content: &str,
+    components: &[Component],
+) -> Result<Id<MessageMarker>, anyhow::Error> {
+    let thread = retry_rate_limited(state.config.reply_rate_limit_retries, || {
+        state
+            .rest
+            .create_thread_from_message(channel_id, message_id, THREAD_NAME)
+            .into_future()
+    })
+    .await?
+    .model()
+    .await?;
+
+    let reply = send_message(state, || {
+        state
+            .rest
+            .create_message(thread.id)
+            .content(content)
+            .components(components)
+            .allowed_mentions(Some(&state.config.allowed_mentions))
+            .into_future()
+    })
+    .await;
+
+    match reply {
+        Ok(reply) => Ok(reply.id),
+        Err(e) => {
+            let deleted =
+                retry_transient(|| state.rest.delete_channel(thread.id).into_future()).await;
+            if let Err(e) = deleted {
+                tracing::warn!(error = ?e, "Couldn't delete the empty thread {}", thread.id);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Launches a background Tokio task to delete the reply to a source message
+/// after `ttl`, dropping its cache entry. The deletion is cancelled if the
+/// source's entry is removed from [State::reply_deletions] before then.
+fn delete_reply_deferred(
+    state: Arc<State>,
+    ttl: Duration,
+    channel_id: Id<ChannelMarker>,
+    source_id: Id<MessageMarker>,
+) -> tokio::task::JoinHandle<()> {
+    let (cancel, cancelled) = oneshot::channel::<()>();
+    let mut reply_deletions = state.reply_deletions.lock().unwrap();
+    reply_deletions.insert(source_id, cancel);
+    drop(reply_deletions);
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep(ttl) => {}
+            // Dropping the sender cancels
+            _ = cancelled => return,
+        }
+
+        state.reply_deletions.lock().unwrap().remove(&source_id);
+        delete_forgotten_reply(&state, channel_id, source_id).await;
+    })
+}
+
+/// Launches a background Tokio task like [delete_reply_deferred], for a reply
+/// that's still being sent. The wait starts once the reply is there, and the
+/// deletion is given up on if it never is.
+fn delete_when_sent(
+    state: Arc<State>,
+    grace: Duration,
+    channel_id: Id<ChannelMarker>,
+    source_id: Id<MessageMarker>,
+) -> tokio::task::JoinHandle<()> {
+    let (cancel, mut cancelled) = oneshot::channel::<()>();
+    let mut reply_deletions = state.reply_deletions.lock().unwrap();
+    reply_deletions.insert(source_id, cancel);
+    drop(reply_deletions);
+
+    tokio::spawn(async move {
+        let sent = tokio::select! {
+            sent = wait_until_sent(&state, source_id) => sent,
+            // Dropping the sender cancels
+            _ = &mut cancelled => return,
+        };
+        if !sent {
+            state.reply_deletions.lock().unwrap().remove(&source_id);
+            return;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(grace) => {}
+            _ = cancelled => return,
+        }
+
+        state.reply_deletions.lock().unwrap().remove(&source_id);
+        delete_forgotten_reply(&state, channel_id, source_id).await;
+    })
+}
+
+/// Deletes the reply to a source message along with any overflow, dropping its
+/// cache entry first so nothing else touches the reply.
+async fn delete_forgotten_reply(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    source_id: Id<MessageMarker>,
+) {
+    let overflow = state.replies.read().unwrap().overflow(source_id).len();
+    if !state.spend_budget(1 + overflow, ApiPriority::Primary) {
+        return;
+    }
+
+    let (entry, overflow) = {
+        let mut replies = state.replies.write().unwrap();
+        let entry = replies.get_entry(source_id);
+        let overflow = replies.take_overflow(source_id);
+        replies.retain(|source, _| source != source_id);
+        (entry, overflow)
+    };
+
+    let reply = entry.and_then(|entry| entry.reply(channel_id, source_id));
+    if let Some((reply_channel_id, reply_id)) = reply {
+        let delete = retry_transient(|| {
+            state
+                .rest
+                .delete_message(reply_channel_id, reply_id)
+                .into_future()
+        });
+
+        let deleted = match delete.await {
+            Ok(_) => delete_overflow(state, reply_channel_id, &overflow).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = deleted {
+            report_error(state, Some(reply_channel_id), &e.into());
+        }
+    }
+}
+
+/// Launches a background Tokio task to [post a reply] after `delay`
+/// milliseconds. The reply is cancelled if the message's entry is removed from
+/// [State::delayed_posts] before then. The content is not updated for edits
+/// made during the delay. The fix of `scanned` is [accounted for] once the
+/// reply goes out.
+///
+/// [post a reply]: post_reply
+/// [accounted for]: fix_sent
+#[allow(clippy::too_many_arguments)]
+fn post_reply_deferred(
+    state: Arc<State>,
+    delay: u64,
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    author_id: Option<Id<UserMarker>>,
+    chunks: Vec<String>,
+    scanned: String,
+) -> tokio::task::JoinHandle<()> {
+    let (cancel, cancelled) = oneshot::channel::<()>();
+    let mut delayed_posts = state.delayed_posts.lock().unwrap();
+    delayed_posts.insert(message_id, cancel);
+    drop(delayed_posts);
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(delay)) => {}
+            // Dropping the sender cancels
+            _ = cancelled => return,
+        }
+
+        state.delayed_posts.lock().unwrap().remove(&message_id);
+        let post = post_reply(&state, guild_id, channel_id, message_id, author_id, &chunks);
+        match post.await {
+            Ok(()) => fix_sent(&state, channel_id, message_id, &scanned),
+            Err(e) => report_error(&state, Some(channel_id), &e),
+        }
+    })
+}
+
+/// Accounts for a fix of `scanned` that was posted: the passes that went into
+/// it [fire], the links are [recorded] as fixed, and every link is given to
+/// the [ShareHook]. Only called once the fix has been posted, so replies that
+/// were dropped or failed aren't counted anywhere.
+///
+/// [fire]: Pass::record_fires
+/// [recorded]: record_fixes
+fn fix_sent(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    source_id: Id<MessageMarker>,
+    scanned: &str,
+) {
+    let passes = state.config.passes_in(channel_id);
+    let options = state.config.apply_options_in(channel_id);
+    Pass::record_fires(&passes, scanned, options);
+    record_fixes(state, channel_id, scanned);
+    for (pass, link) in Pass::links(&passes, scanned, options) {
+        state.share_hook.shared(SharedLink {
+            source_id,
+            pass_label: &pass.label,
+            path: link.path,
+            status_id: link.status_id(),
+        });
+    }
+}
+
+/// Whether an embed Discord generated for a link is good enough that the bot
+/// doesn't need to post a fix, which is when it has media.
+fn embed_is_acceptable(embed: &Embed) -> bool {
+    embed.image.is_some() || embed.video.is_some()
+}
+
+/// The text the passes run over: the message content, plus the descriptions
+/// and URLs of its embeds with `scan_embeds`. Words from embeds that already
+/// appeared are left out, so a link in both places is only fixed once.
+fn scanned_text<'a>(content: &'a str, embeds: &[Embed], scan_embeds: bool) -> Cow<'a, str> {
+    if !scan_embeds || embeds.is_empty() {
+        return Cow::Borrowed(content);
+    }
+
+    let mut seen: HashSet<&str> = content.split_whitespace().collect();
+    let mut text = content.to_owned();
+    let fields = embeds
+        .iter()
+        .flat_map(|embed| [embed.description.as_deref(), embed.url.as_deref()])
+        .flatten();
+    for word in fields.flat_map(str::split_whitespace) {
+        if seen.insert(word) {
+            text.push('\n');
+            text.push_str(word);
+        }
+    }
+
+    Cow::Owned(text)
+}
+
+/// Remembers whether a channel's topic turns the bot off in it.
+fn refresh_channel(state: &State, channel: &Channel) {
+    let mut disabled_channels = state.disabled_channels.write().unwrap();
+    if channel.topic.as_deref().is_some_and(topic_disables) {
+        if disabled_channels.insert(channel.id) {
+            tracing::info!("Disabled in channel {} by its topic", channel.id);
+        }
+    } else if disabled_channels.remove(&channel.id) {
+        tracing::info!("Re-enabled in channel {} by its topic", channel.id);
+    }
+}
+
+/// The first millisecond of 2015, which Discord snowflakes count from.
+const DISCORD_EPOCH_MILLIS: u64 = 1_420_070_400_000;
+
+/// When something was created, like a message being sent or a user signing
+/// up, from the timestamp in its ID.
+fn created_at<T>(id: Id<T>) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis((id.get() >> 22) + DISCORD_EPOCH_MILLIS)
+}
+
+/// How long ago something was created as of `now`, from the timestamp in its
+/// ID.
+fn snowflake_age<T>(id: Id<T>, now: SystemTime) -> Duration {
+    now.duration_since(created_at(id)).unwrap_or_default()
+}
+
+/// How long someone has been in a guild as of `now`, given when they joined.
+fn member_age(joined_at: Timestamp, now: SystemTime) -> Duration {
+    let joined =
+        UNIX_EPOCH + Duration::from_micros(u64::try_from(joined_at.as_micros()).unwrap_or(0));
+    now.duration_since(joined).unwrap_or_default()
+}
+
+/// Whether every link the `passes` find in `content` was already fixed in the
+/// channel within `dedup_window_secs`. Links only count once they're [recorded].
+///
+/// [recorded]: record_fixes
+fn is_repeat(
+    state: &State,
+    passes: &[&Pass],
+    channel_id: Id<ChannelMarker>,
+    content: &str,
+) -> bool {
+    if state.config.dedup_window_secs.is_none() {
+        return false;
+    }
+
+    let links = normalized_links(passes, content);
+    let mut recent_fixes = state.recent_fixes.lock().unwrap();
+    recent_fixes.all_recent(channel_id, &links, Instant::now())
+}
+
+/// Remembers the links in `content` as fixed in the channel now, for
+/// [is_repeat]. Only called once the fix has been posted, so a fix that was
+/// dropped or failed doesn't hold back the next one.
+fn record_fixes(state: &State, channel_id: Id<ChannelMarker>, content: &str) {
+    if state.config.dedup_window_secs.is_none() {
+        return;
+    }
+
+    let passes = state.config.passes_in(channel_id);
+    let links = normalized_links(&passes, content);
+    let mut recent_fixes = state.recent_fixes.lock().unwrap();
+    recent_fixes.record(channel_id, links, Instant::now());
+}
+
+/// The [normalized URL] of every link the `passes` find in `content`.
+///
+/// [normalized URL]: Pass::normalized_url
+fn normalized_links(passes: &[&Pass], content: &str) -> Vec<String> {
+    passes
+        .iter()
+        .flat_map(|pass| pass.extract(content).map(|link| pass.normalized_url(&link)))
+        .collect()
+}
+
+/// The most text channels read back per guild with `history_preload`, which
+/// bounds the requests made on startup.
+const PRELOAD_CHANNELS: usize = 50;
+
+/// The most messages Discord returns from a channel's history in one request.
+const HISTORY_PAGE_LIMIT: usize = 100;
+
+/// Fills the reply cache with the bot's replies in the recent history of a
+/// guild's most recently active text channels, so edits and deletes of messages
+/// replied to before a restart are still handled.
+async fn preload_history(state: &State, channels: &[Channel]) {
+    let Some(&user_id) = state.user_id.get() else {
+        return;
+    };
+
+    let mut channels: Vec<_> = channels
+        .iter()
+        .filter(|channel| {
+            matches!(
+                channel.kind,
+                ChannelType::GuildText | ChannelType::GuildAnnouncement
+            )
+        })
+        .collect();
+    channels.sort_by_key(|channel| Reverse(channel.last_message_id));
+
+    let limit = state.config.history_preload.min(HISTORY_PAGE_LIMIT) as u16;
+    let mut replies = Vec::new();
+    for channel in channels.into_iter().take(PRELOAD_CHANNELS) {
+        match channel_history(state, channel.id, limit).await {
+            Ok(history) => replies.extend(own_replies(user_id, channel.id, &history)),
+            // Usually a channel the bot can't read
+            Err(e) => tracing::debug!(error = ?e, "Couldn't read history of {}", channel.id),
+        }
+    }
+
+    // Oldest first, so the newest stay if there are more than fit
+    replies.sort_unstable();
+    let mut cache = state.replies.write().unwrap();
+    let mut preloaded = 0;
+    for (source, reply) in replies {
+        // Anything already there was handled since we connected
+        if let Some(token) = cache.file_pending(source) {
+            cache.insert(token, reply);
+            preloaded += 1;
+        }
+    }
+
+    tracing::info!("Preloaded {preloaded} replies from history");
+}
+
+/// Gets up to `limit` of the most recent messages in a channel.
+async fn channel_history(
+    state: &State,
+    channel_id: Id<ChannelMarker>,
+    limit: u16,
+) -> Result<Vec<Message>, anyhow::Error> {
+    let history = retry_transient(|| {
+        state
+            .rest
+            .channel_messages(channel_id)
+            .limit(limit)
+            .into_future()
+    })
+    .await?
+    .models()
+    .await?;
+
+    Ok(history)
+}
+
+/// Finds the replies `user_id` sent in a channel's history, as pairs of the
+/// source message and the reply. Replies are recognized by the source they
+/// reference, so ones in threads or the `mirror_channel` aren't found.
+fn own_replies(
+    user_id: Id<UserMarker>,
+    channel_id: Id<ChannelMarker>,
+    history: &[Message],
+) -> Vec<(Id<MessageMarker>, Id<MessageMarker>)> {
+    history
+        .iter()
+        .filter(|message| message.author.id == user_id)
+        .filter_map(|message| {
+            let reference = message.reference.as_ref()?;
+            // Forwards and crossposts reference messages in other channels
+            if reference.channel_id.is_some_and(|id| id != channel_id) {
+                return None;
+            }
+
+            Some((reference.message_id?, message.id))
+        })
+        .collect()
+}
+
+/// The reaction added to messages skipped for repeating a recent fix.
+const REPEAT_REACTION: RequestReactionType = RequestReactionType::Unicode { name: "🔁" };
+
+/// What a reply is edited to with `on_empty_rewrite = "clear_content"`.
+const EMPTY_REWRITE_PLACEHOLDER: &str = "*Links removed*";
+
+/// What owners send to pause the bot for maintenance.
+const PAUSE_COMMAND: &str = "!tweetboat pause";
+
+/// What owners send to unpause the bot.
+const RESUME_COMMAND: &str = "!tweetboat resume";
+
+/// What owners send to see how often each pass fired.
+const STATS_COMMAND: &str = "!tweetboat stats";
+
+/// What owners send, followed by a number of milliseconds, to change
+/// `suppress_delay_millis` without restarting.
+const SET_SUPPRESS_DELAY_COMMAND: &str = "!tweetboat set suppress_delay";
+
+/// The longest suppress delay owners can set, in milliseconds.
+const MAX_SUPPRESS_DELAY_MILLIS: u64 = 60_000;
+
+/// The reaction acknowledging an owner's command.
+const COMMAND_REACTION: RequestReactionType = RequestReactionType::Unicode { name: "✅" };
+
+/// Something owners can tell the bot to do.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum OwnerCommand {
+    Pause,
+    Resume,
+    Stats,
+    SetSuppressDelay(u64),
+}
+
+/// Parses an owner command from a message's content, giving `None` if it
+/// isn't one, or an explanation if it's one with a bad argument.
+fn parse_owner_command(content: &str) -> Option<Result<OwnerCommand, String>> {
+    let content = content.trim();
+    match content {
+        PAUSE_COMMAND => return Some(Ok(OwnerCommand::Pause)),
+        RESUME_COMMAND => return Some(Ok(OwnerCommand::Resume)),
+        STATS_COMMAND => return Some(Ok(OwnerCommand::Stats)),
+        _ => {}
+    }
+
+    let value = content
+        .strip_prefix(SET_SUPPRESS_DELAY_COMMAND)
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))?
+        .trim();
+    let parsed = match value.parse::<u64>() {
+        Ok(millis) if millis <= MAX_SUPPRESS_DELAY_MILLIS => {
+            Ok(OwnerCommand::SetSuppressDelay(millis))
+        }
+        _ => Err(format!(
+            "The suppress delay needs to be a number of milliseconds up to {MAX_SUPPRESS_DELAY_MILLIS}, like `{SET_SUPPRESS_DELAY_COMMAND} 200`."
+        )),
+    };
+    Some(parsed)
+}
+
+/// Carries out `message` if it's an owner's command, returning whether it was.
+/// Entering and leaving maintenance are logged once each, so repeating a
+/// command does nothing but acknowledge it again. Setting the suppress delay
+/// is confirmed with a reply, as is a command the bot couldn't make sense of,
+/// and stats are given in one.
+async fn owner_command(state: &State, message: &Message) -> Result<bool, anyhow::Error> {
+    if !state.config.owners.contains(&message.author.id) {
+        return Ok(false);
+    }
+
+    let Some(command) = parse_owner_command(&message.content) else {
+        return Ok(false);
+    };
+    record_decision("owner command");
+
+    let paused = match command {
+        Ok(OwnerCommand::Pause) => true,
+        Ok(OwnerCommand::Resume) => false,
+        Ok(OwnerCommand::Stats) => {
+            owner_reply(state, message, &pass_stats(&state.config)).await?;
+            return Ok(true);
+        }
+        Ok(OwnerCommand::SetSuppressDelay(millis)) => {
+            let old = state.suppress_delay_millis.swap(millis, Ordering::Relaxed);
+            tracing::info!("Suppress delay changed from {old}ms to {millis}ms");
+            let confirmation = format!("Suppress delay set to {millis}ms (was {old}ms).");
+            owner_reply(state, message, &confirmation).await?;
+            return Ok(true);
+        }
+        Err(explanation) => {
+            owner_reply(state, message, &explanation).await?;
+            return Ok(true);
+        }
+    };
+    if state.paused.swap(paused, Ordering::Relaxed) != paused {
+        if paused {
+            tracing::warn!("Entering maintenance, leaving messages alone");
+        } else {
+            tracing::info!("Leaving maintenance, fixing messages again");
+        }
+    }
+
+    retry_transient(|| {
+        state
+            .rest
+            .create_reaction(message.channel_id, message.id, &COMMAND_REACTION)
+            .into_future()
+    })
+    .await?;
+    Ok(true)
+}
+
+/// How many replies each pass went into since the bot started, one pass per
+/// line.
+fn pass_stats(config: &Config) -> String {
+    let lines: Vec<_> = config
+        .passes
+        .iter()
+        .map(|pass| format!("`{}`: {} fixes", pass.label, pass.fire_count()))
+        .collect();
+    lines.join("\n")
+}
+
+/// Replies to an owner's command with `content`.
+async fn owner_reply(state: &State, message: &Message, content: &str) -> Result<(), anyhow::Error> {
+    retry_transient(|| {
+        state
+            .rest
+            .create_message(message.channel_id)
+            .content(content)
+            .reply(message.id)
+            .allowed_mentions(Some(&state.config.allowed_mentions))
+            .into_future()
+    })
+    .await?;
+    Ok(())
+}
+
+async fn dispatch_event(state: Arc<State>, event: Event) -> Result<(), anyhow::Error> {
+    if let Event::MessageCreate(message) = &event {
+        if owner_command(&state, message).await? {
+            return Ok(());
+        }
+    }
+
+    let messages = matches!(
+        event,
+        Event::MessageCreate(_) | Event::MessageUpdate(_) | Event::MessageDelete(_)
+    );
+    if messages && state.paused.load(Ordering::Relaxed) {
+        record_decision("maintenance");
+        return Ok(());
+    }
+
+    match event {
+        // CREATE: Fix embeds when someone sends a twitter link
+        Event::MessageCreate(message) => {
+            if message.author.bot || state.config.ignored_users.contains(&message.author.id) {
+                record_decision("ignored author");
+                return Ok(());
+            }
+
+            if state.config.ignore_replies && message.referenced_message.is_some() {
+                record_decision("ignored reply");
+                return Ok(());
+            }
+
+            let disabled = state
+                .disabled_channels
+                .read()
+                .unwrap()
+                .contains(&message.channel_id);
+            if disabled {
+                record_decision("disabled channel");
+                return Ok(());
+            }
+
+            if let Some(max_age) = state.config.max_message_age_secs {
+                if snowflake_age(message.id, SystemTime::now()) > Duration::from_secs(max_age) {
+                    tracing::debug!("Skipping old message {}", message.id);
+                    record_decision("too old");
+                    return Ok(());
+                }
+            }
+
+            // Spam accounts tend to be brand new
+            if let Some(min_age) = state.config.min_account_age_secs {
+                let age = snowflake_age(message.author.id, SystemTime::now());
+                if age < Duration::from_secs(min_age) {
+                    tracing::debug!(
+                        "Skipping {} from new account {}",
+                        message.id,
+                        message.author.id
+                    );
+                    record_decision("new account");
+                    return Ok(());
+                }
+            }
+            if let Some(min_age) = state.config.min_member_age_secs {
+                let joined_at = message.member.as_ref().and_then(|member| member.joined_at);
+                let now = SystemTime::now();
+                if joined_at.is_some_and(|joined_at| {
+                    member_age(joined_at, now) < Duration::from_secs(min_age)
+                }) {
+                    tracing::debug!(
+                        "Skipping {} from new member {}",
+                        message.id,
+                        message.author.id
+                    );
+                    record_decision("new member");
+                    return Ok(());
+                }
+            }
+
+            // Existing replies are still kept up to date
+            let quiet = state.config.quiet_hours;
+            if quiet.is_some_and(|quiet| quiet.contains(created_at(message.id))) {
+                tracing::debug!("Skipping {} during quiet hours", message.id);
+                record_decision("quiet hours");
+                return Ok(());
+            }
+
+            // Another instance of the bot could pass the author check, so never
+            // fix one of our own replies
+            if state.replies.read().unwrap().is_reply(message.id) {
+                tracing::debug!("Skipping our own reply {}", message.id);
+                record_decision("own reply");
+                return Ok(());
+            }
+
+            // Discord may redeliver events after a resume, so anything already
+            // in the cache has been handled
+            let cached = state.replies.read().unwrap().get_entry(message.id);
+            let delayed = state
+                .delayed_posts
+                .lock()
+                .unwrap()
+                .contains_key(&message.id);
+            if cached.is_some() || delayed {
+                tracing::debug!("Skipping redelivered message {}", message.id);
+                record_decision("redelivered");
+                return Ok(());
+            }
+
+            let scanned = scanned_text(&message.content, &message.embeds, state.config.scan_embeds);
+            let passes = state.config.passes_in(message.channel_id);
+            let options = state.config.apply_options_in(message.channel_id);
+            let rewrite = Pass::apply_all(&passes, &scanned, options);
+            record_matched(matches!(rewrite, Rewrite::Fixed(_)));
+            if let Rewrite::Fixed(content) = rewrite {
+                let delay = state.config.post_delay_millis;
+                if delay > 0 && message.embeds.iter().any(embed_is_acceptable) {
+                    tracing::info!("Embed on {} already works, not rewriting", message.id);
+                    record_decision("embed works");
+                    return Ok(());
+                }
+
+                if is_repeat(&state, &passes, message.channel_id, &scanned) {
+                    tracing::info!("Links in {} were just fixed, not rewriting", message.id);
+                    record_decision("repeat");
+                    if state.config.dedup_reaction && state.spend_budget(1, ApiPriority::Low) {
+                        retry_transient(|| {
+                            state
+                                .rest
+                                .create_reaction(message.channel_id, message.id, &REPEAT_REACTION)
+                                .into_future()
+                        })
+                        .await?;
+                    }
+
+                    return Ok(());
+                }
+
+                tracing::info!("Rewriting {:?} => {content:?}", message.content);
+                if state.config.trace_events {
+                    // Easier to follow than the above with several links
+                    tracing::debug!("Rewrite diff: {}", word_diff(&message.content, &content));
+                }
+                let chunks = reply_chunks(&state, &passes, &scanned, options, content);
+                let calls = chunks.len() + usize::from(!message.embeds.is_empty());
+                if !state.spend_budget(calls, ApiPriority::Primary) {
+                    record_decision("over budget");
+                    return Ok(());
+                }
+
+                // Nothing is left to suppress or wait on once the message is gone
+                if state.config.delete_original {
+                    let (channel_id, author_id) = (message.channel_id, message.author.id);
+                    if replace_original(&state, channel_id, message.id, author_id, &chunks).await? {
+                        record_decision("replace original");
+                        fix_sent(&state, channel_id, message.id, &scanned);
+                        return Ok(());
+                    }
+                }
+
+                // If the unfurler has an embed cached, embeds will be included
+                let suppress = (!message.embeds.is_empty()).then(|| {
+                    suppress_embeds_deferred(
+                        &state.rest,
+                        &state.suppress_permits,
+                        state.suppress_delay(),
+                        state.config.suppress_flags,
+                        message.channel_id,
+                        message.id,
+                    )
+                });
+
+                if delay > 0 {
+                    record_decision("delayed reply");
+                    if let Some(suppress) = suppress {
+                        watch_suppression(&state, suppress, message.channel_id, message.id);
+                    }
+                    post_reply_deferred(
+                        state,
+                        delay,
+                        message.guild_id,
+                        message.channel_id,
+                        message.id,
+                        Some(message.author.id),
+                        chunks,
+                        scanned.into_owned(),
+                    );
+                } else {
+                    record_decision("reply");
+                    post_reply(
+                        &state,
+                        message.guild_id,
+                        message.channel_id,
+                        message.id,
+                        Some(message.author.id),
+                        &chunks,
+                    )
+                    .await?;
+                    fix_sent(&state, message.channel_id, message.id, &scanned);
+                    // Only once the reply is sent, so there's one to remove
+                    if let Some(suppress) = suppress {
+                        watch_suppression(&state, suppress, message.channel_id, message.id);
+                    }
+                }
+            } else {
+                record_decision("no links");
+            }
+        }
+
+        // UPDATE: Edit our reply when someone edits a link in/out
+        Event::MessageUpdate(message) => {
+            // Partial updates (like embeds loading) don't say who the author is
+            let ignored = message.author.as_ref().is_some_and(|author| {
+                author.bot || state.config.ignored_users.contains(&author.id)
+            });
+            if ignored {
+                record_decision("ignored author");
+                return Ok(());
+            }
+
+            let delayed = state
+                .delayed_posts
+                .lock()
+                .unwrap()
+                .contains_key(&message.id);
+            if delayed {
+                if let Some(embeds) = message.embeds {
+                    if embeds.iter().any(embed_is_acceptable) {
+                        tracing::info!("Embed on {} loaded fine, cancelling reply", message.id);
+                        record_decision("cancel delayed reply");
+                        state.delayed_posts.lock().unwrap().remove(&message.id);
+                    } else if !embeds.is_empty() && state.spend_budget(1, ApiPriority::Primary) {
+                        let suppress = suppress_embeds_deferred(
+                            &state.rest,
+                            &state.suppress_permits,
+                            state.suppress_delay(),
+                            state.config.suppress_flags,
+                            message.channel_id,
+                            message.id,
+                        );
+                        watch_suppression(&state, suppress, message.channel_id, message.id);
+                    }
+                }
+
+                return Ok(());
+            }
+
+            let entry = state.replies.read().unwrap().get_entry(message.id);
+            let Some(entry) = entry else {
+                record_decision("not cached");
+                return Ok(());
+            };
+
+            // Suppress embeds the unfurler provided lazily
+            if message
+                .embeds
+                .as_ref()
+                .is_some_and(|embeds| !embeds.is_empty())
+                && state.spend_budget(1, ApiPriority::Primary)
+            {
+                tracing::info!("Unfurler triggered on {:?}, suppressing...", entry);
+                let suppress = suppress_embeds_deferred(
+                    &state.rest,
+                    &state.suppress_permits,
+                    state.suppress_delay(),
+                    state.config.suppress_flags,
+                    message.channel_id,
+                    message.id,
+                );
+                watch_suppression(&state, suppress, message.channel_id, message.id);
+            };
+
+            if let Some((reply_channel_id, reply_id)) = entry.reply(message.channel_id, message.id)
+            {
+                if let Some(content) = message.content {
+                    // Someone's still working on the message, so keep the reply
+                    state.reply_deletions.lock().unwrap().remove(&message.id);
+
+                    let author_id = message.author.as_ref().map(|author| author.id);
+                    let embeds = message.embeds.as_deref().unwrap_or_default();
+                    let scanned = scanned_text(&content, embeds, state.config.scan_embeds);
+                    let passes = state.config.passes_in(message.channel_id);
+                    let options = state.config.apply_options_in(message.channel_id);
+                    let rewrite = Pass::apply_all(&passes, &scanned, options);
+                    record_matched(matches!(rewrite, Rewrite::Fixed(_)));
+                    if let Rewrite::Fixed(content) = rewrite {
+                        // Charged when the edit is made, so a burst is charged once
+                        let chunks = reply_chunks(&state, &passes, &scanned, options, content);
+                        let window = state.config.edit_coalesce_millis;
+                        if window > 0 {
+                            record_decision("coalesced edit");
+                            edit_reply_deferred(
+                                Arc::clone(&state),
+                                window,
+                                message.guild_id,
+                                message.channel_id,
+                                message.id,
+                                author_id,
+                                chunks,
+                            );
+                        } else {
+                            record_decision("edit");
+                            edit_reply(
+                                &state,
+                                message.guild_id,
+                                message.channel_id,
+                                message.id,
+                                author_id,
+                                &chunks,
+                            )
+                            .await?;
+                        }
+                    } else if rewrite == Rewrite::Filtered && state.config.keep_filtered_replies {
+                        tracing::debug!("Links in {} were all skipped, keeping reply", message.id);
+                        record_decision("keep");
+                    } else if state.config.on_empty_rewrite == EmptyRewrite::Keep {
+                        tracing::debug!("Links in {} were all removed, keeping reply", message.id);
+                        record_decision("keep");
+                    } else if state.config.on_empty_rewrite == EmptyRewrite::ClearContent {
+                        record_decision("clear");
+                        state.pending_edits.lock().unwrap().remove(&message.id);
+                        edit_reply(
+                            &state,
+                            message.guild_id,
+                            message.channel_id,
+                            message.id,
+                            author_id,
+                            &[EMPTY_REWRITE_PLACEHOLDER.to_owned()],
+                        )
+                        .await?;
+                    } else if state.config.edit_out_grace_millis > 0 {
+                        record_decision("delete after grace");
+                        state.pending_edits.lock().unwrap().remove(&message.id);
+                        // The links may be back in a moment, which cancels this
+                        let grace = Duration::from_millis(state.config.edit_out_grace_millis);
+                        delete_reply_deferred(
+                            Arc::clone(&state),
+                            grace,
+                            message.channel_id,
+                            message.id,
+                        );
+                    } else {
+                        let overflow = state.replies.read().unwrap().overflow(message.id).len();
+                        if !state.spend_budget(1 + overflow, ApiPriority::Primary) {
+                            record_decision("over budget");
+                            return Ok(());
+                        }
+
+                        record_decision("delete");
+                        state.pending_edits.lock().unwrap().remove(&message.id);
+                        // Forgotten like after a grace, so nothing touches the deleted reply
+                        let overflow = {
+                            let mut replies = state.replies.write().unwrap();
+                            let overflow = replies.take_overflow(message.id);
+                            replies.retain(|source, _| source != message.id);
+                            overflow
+                        };
+                        retry_transient(|| {
+                            state
+                                .rest
+                                .delete_message(reply_channel_id, reply_id)
+                                .into_future()
+                        })
+                        .await?;
+                        delete_overflow(&state, reply_channel_id, &overflow).await?;
+                    }
+                }
+            } else if entry == CacheEntry::Pending {
+                // The reply is still being sent, so edit it once it's there
+                if let Some(content) = message.content {
+                    let author_id = message.author.as_ref().map(|author| author.id);
+                    let embeds = message.embeds.as_deref().unwrap_or_default();
+                    let scanned = scanned_text(&content, embeds, state.config.scan_embeds);
+                    let passes = state.config.passes_in(message.channel_id);
+                    let options = state.config.apply_options_in(message.channel_id);
+                    let rewrite = Pass::apply_all(&passes, &scanned, options);
+                    record_matched(matches!(rewrite, Rewrite::Fixed(_)));
+                    if let Rewrite::Fixed(content) = rewrite {
+                        // The links are back before the reply was deleted
+                        state.reply_deletions.lock().unwrap().remove(&message.id);
+
+                        let chunks = reply_chunks(&state, &passes, &scanned, options, content);
+                        record_decision("edit once sent");
+                        edit_when_sent(
+                            Arc::clone(&state),
+                            message.guild_id,
+                            message.channel_id,
+                            message.id,
+                            author_id,
+                            chunks,
+                        );
+                    } else if (rewrite == Rewrite::Filtered && state.config.keep_filtered_replies)
+                        || state.config.on_empty_rewrite == EmptyRewrite::Keep
+                    {
+                        record_decision("keep");
+                    } else if state.config.on_empty_rewrite == EmptyRewrite::ClearContent {
+                        record_decision("clear once sent");
+                        edit_when_sent(
+                            Arc::clone(&state),
+                            message.guild_id,
+                            message.channel_id,
+                            message.id,
+                            author_id,
+                            vec![EMPTY_REWRITE_PLACEHOLDER.to_owned()],
+                        );
+                    } else {
+                        record_decision("delete once sent");
+                        state.pending_edits.lock().unwrap().remove(&message.id);
+                        let grace = Duration::from_millis(state.config.edit_out_grace_millis);
+                        delete_when_sent(Arc::clone(&state), grace, message.channel_id, message.id);
+                    }
+                }
+            }
+        }
+
+        // DELETE: Delete our reply when someone deletes their source message
+        Event::MessageDelete(message) => {
+            state.delayed_posts.lock().unwrap().remove(&message.id);
+            state.reply_deletions.lock().unwrap().remove(&message.id);
+            state.pending_edits.lock().unwrap().remove(&message.id);
+            let (entry, overflow) = {
+                let mut replies = state.replies.write().unwrap();
+                (
+                    replies.take_entry(message.id),
+                    replies.take_overflow(message.id),
+                )
+            };
+
+            // Temporary extension with `if let` pulls the guard across the await
+            // boundary as it keeps the temp. alive for the entire scope, so we need
+            // to separate it
+            let reply = entry.and_then(|entry| entry.reply(message.channel_id, message.id));
+            record_decision(if reply.is_some() {
+                "delete"
+            } else {
+                "not cached"
+            });
+            if let Some((reply_channel_id, reply_id)) = reply {
+                if !state.spend_budget(1 + overflow.len(), ApiPriority::Primary) {
+                    return Ok(());
+                }
+
+                retry_transient(|| {
+                    state
+                        .rest
+                        .delete_message(reply_channel_id, reply_id)
+                        .into_future()
+                })
+                .await?;
+                delete_overflow(&state, reply_channel_id, &overflow).await?;
+            }
+        }
+
+        // INTERACTION: Delete a reply when its source's author clicks its button
+        Event::InteractionCreate(interaction) => {
+            let Some(InteractionData::MessageComponent(data)) = &interaction.data else {
+                return Ok(());
+            };
+            if let Some((source_id, author_id)) = parse_delete_button(&data.custom_id) {
+                delete_from_button(&state, &interaction, source_id, author_id).await?;
+            }
+        }
+
+        // READY: Remember who we are to recognize our replies in history
+        Event::Ready(ready) => {
+            let _ = state.user_id.set(ready.user.id);
+        }
+
+        // GUILD/CHANNEL: Keep track of channels that turned the bot off, and
+        // find replies from before a restart
+        Event::GuildCreate(guild) => {
+            if state.config.topic_directives {
+                for channel in &guild.channels {
+                    refresh_channel(&state, channel);
+                }
+            }
+
+            if state.config.history_preload > 0 {
+                // Reading history takes a while, so don't hold up other events
+                let channels = guild.channels.clone();
+                tokio::spawn(async move { preload_history(&state, &channels).await });
+            }
+        }
+        Event::ChannelCreate(channel) if state.config.topic_directives => {
+            refresh_channel(&state, &channel);
+        }
+        Event::ChannelUpdate(channel) if state.config.topic_directives => {
+            refresh_channel(&state, &channel);
+        }
+        Event::ChannelDelete(channel) => {
+            state.disabled_channels.write().unwrap().remove(&channel.id);
+        }
+
+        // RESUMED: Replayed events are handled by the cache, so keep it intact
+        Event::Resumed => {
+            tracing::info!("Gateway session resumed");
+        }
+
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::future::IntoFuture;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde_json::json;
+    use tokio::sync::Semaphore;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Record};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt as _};
+    use twilight_model::channel::message::MessageFlags;
+    use twilight_model::channel::Message;
+    use twilight_model::gateway::event::Event;
+    use twilight_model::gateway::payload::incoming::InteractionCreate;
+    use twilight_model::id::{marker::UserMarker, Id};
+    use twilight_model::util::Timestamp;
+
+    use tweetboat::cache::CacheEntry;
+    use tweetboat::config::Config;
+    use tweetboat::error::ErrorClass;
+    use tweetboat::hook::SharedLink;
+
+    use crate::mock::{self, MockDiscord};
+    use crate::{
+        delete_reply_deferred, dispatch_event, jump_url, member_age, own_replies,
+        parse_delete_button, parse_owner_command, retry_transient, snowflake_age, spawn_dispatch,
+        suppress_embeds_deferred, OwnerCommand, State, DISCORD_EPOCH_MILLIS,
+    };
+
+    /// Builds a [State] around the example config, with the top-level keys in
+    /// `overrides` replacing its own.
+    fn state(mock: &MockDiscord, overrides: &str) -> Arc<State> {
+        let mut config: toml::Table = include_str!("../config.example.toml").parse().unwrap();
+        config.extend(overrides.parse::<toml::Table>().unwrap());
+        let config: Config = config.try_into().unwrap();
+
+        State::builder(config)
+            .rest(mock.client())
+            .jitter_seed(0)
+            .build()
+    }
+
+    /// Collects the fields of every span as they're recorded, by name.
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<HashMap<&'static str, String>>>);
+
+    impl SpanFields {
+        fn get(&self, name: &str) -> Option<String> {
+            self.0.lock().unwrap().get(name).cloned()
+        }
+    }
+
+    impl Visit for SpanFields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name(), value.to_owned());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let value = format!("{value:?}");
+            self.0.lock().unwrap().insert(field.name(), value);
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for SpanFields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &tracing::span::Id, _: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _: &tracing::span::Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn state_builder() {
+        let mock = MockDiscord::spawn().await;
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        let state = State::builder(config)
+            .rest(mock.client())
+            .reply_cache_size(4)
+            .build();
+
+        // The fifth reply pushes the first out of the smaller cache
+        for id in 1..=5 {
+            let event = mock::message_create(id, 1, "https://x.com/user/status/1");
+            dispatch_event(Arc::clone(&state), event).await.unwrap();
+        }
+
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 5);
+        let replies = state.replies.read().unwrap();
+        assert!(replies.get_entry(Id::new(1)).is_none());
+        assert!(replies.get_entry(Id::new(5)).is_some());
+    }
+
+    #[tokio::test]
+    async fn redelivered_create() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "");
+
+        let event = || mock::message_create(1, 1, "https://x.com/user/status/1");
+        dispatch_event(Arc::clone(&state), event()).await.unwrap();
+        dispatch_event(Arc::clone(&state), Event::Resumed)
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&state), event()).await.unwrap();
+
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+    }
+
+    #[tokio::test]
+    async fn update_from_bot() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "");
+
+        dispatch_event(
+            Arc::clone(&state),
+            mock::message_create(1, 1, "https://x.com/user/status/1"),
+        )
+        .await
+        .unwrap();
+
+        let mut edit = mock::message(1, 1, "https://x.com/user/status/2");
+        edit["author"]["bot"] = json!(true);
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 0);
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+    }
+
+    #[tokio::test]
+    async fn scan_embeds() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "scan_embeds = true");
+
+        let mut message = mock::message(1, 1, "");
+        message["embeds"] = json!([{
+            "type": "rich",
+            "description": "posted https://x.com/user/status/1",
+        }]);
+        dispatch_event(Arc::clone(&state), mock::create(message))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+
+        // A link in both the content and an embed is only fixed once
+        let link = "https://x.com/user/status/2";
+        let mut message = mock::message(2, 1, link);
+        message["embeds"] = json!([{
+            "type": "rich",
+            "url": link,
+            "description": "and https://x.com/user/status/3",
+        }]);
+        dispatch_event(Arc::clone(&state), mock::create(message))
+            .await
+            .unwrap();
+
+        let requests = mock.requests();
+        let (_, _, body) = requests
+            .iter()
+            .rfind(|(method, path, _)| method == "POST" && path == "/channels/1/messages")
+            .unwrap();
+        assert_eq!(body.matches("/user/status/2").count(), 1);
+        assert!(body.contains("/user/status/3"));
+    }
+
+    #[tokio::test]
+    async fn share_hook() {
+        let mock = MockDiscord::spawn().await;
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        let shared = Arc::new(Mutex::new(Vec::new()));
+        let hook = {
+            let shared = Arc::clone(&shared);
+            move |link: SharedLink| {
+                shared.lock().unwrap().push((
+                    link.source_id,
+                    link.pass_label.to_owned(),
+                    link.path.to_owned(),
+                    link.status_id.map(str::to_owned),
+                ));
+            }
+        };
+        let state = State::builder(config)
+            .rest(mock.client())
+            .share_hook(hook)
+            .build();
+
+        let content = "https://x.com/user/status/123?s=20 https://www.instagram.com/reel/abc/";
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, content))
+            .await
+            .unwrap();
+        assert_eq!(
+            *shared.lock().unwrap(),
+            [
+                (
+                    Id::new(1),
+                    "Tweet".to_owned(),
+                    "/user/status/123".to_owned(),
+                    Some("123".to_owned())
+                ),
+                (
+                    Id::new(1),
+                    "Instagram Post".to_owned(),
+                    "/reel/abc/".to_owned(),
+                    None
+                ),
+            ]
+        );
+
+        // Nothing is shared for a reply that didn't go out
+        shared.lock().unwrap().clear();
+        mock.fail_next("POST", 403);
+        let failed = dispatch_event(Arc::clone(&state), mock::message_create(2, 1, content)).await;
+        assert!(failed.is_err());
+        assert!(shared.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn maintenance() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "maintenance = true\nowners = [\"3\"]");
+        let link = "https://x.com/user/status/1";
+
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        let edit = mock::message(1, 1, "https://x.com/user/status/2");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        let delete = json!({ "id": "1", "channel_id": "1" });
+        let delete = Event::MessageDelete(serde_json::from_value(delete).unwrap());
+        dispatch_event(Arc::clone(&state), delete).await.unwrap();
+        assert!(mock.requests().is_empty());
+
+        // Only owners can unpause it
+        let command = |id, author: &str| {
+            let mut message = mock::message(id, 1, "!tweetboat resume");
+            message["author"]["id"] = json!(author);
+            mock::create(message)
+        };
+        dispatch_event(Arc::clone(&state), command(2, "2"))
+            .await
+            .unwrap();
+        assert!(mock.requests().is_empty());
+        dispatch_event(Arc::clone(&state), command(3, "3"))
+            .await
+            .unwrap();
+        assert_eq!(
+            mock.count("PUT", "/channels/1/messages/3/reactions/%E2%9C%85/@me"),
+            1
+        );
+
+        dispatch_event(Arc::clone(&state), mock::message_create(4, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+    }
+
+    #[test]
+    fn owner_commands() {
+        let parse = |content| parse_owner_command(content).map(|command| command.is_ok());
+        assert_eq!(
+            parse_owner_command(" !tweetboat pause "),
+            Some(Ok(OwnerCommand::Pause))
+        );
+        assert_eq!(
+            parse_owner_command("!tweetboat set suppress_delay 350"),
+            Some(Ok(OwnerCommand::SetSuppressDelay(350)))
+        );
+        assert_eq!(
+            parse_owner_command("!tweetboat stats"),
+            Some(Ok(OwnerCommand::Stats))
+        );
+        assert_eq!(parse("!tweetboat set suppress_delay"), Some(false));
+        assert_eq!(parse("!tweetboat set suppress_delay -5"), Some(false));
+        assert_eq!(parse("!tweetboat set suppress_delay 600000"), Some(false));
+        assert_eq!(parse("!tweetboat set suppress_delayed 5"), None);
+        assert_eq!(parse("https://x.com/user/status/1"), None);
+    }
+
+    #[tokio::test]
+    async fn set_suppress_delay() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "owners = [\"3\"]");
+        let command = |id, content| {
+            let mut message = mock::message(id, 1, content);
+            message["author"]["id"] = json!("3");
+            mock::create(message)
+        };
+
+        dispatch_event(
+            Arc::clone(&state),
+            command(1, "!tweetboat set suppress_delay 0"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state.suppress_delay(), 0);
+        let requests = mock.requests();
+        let (_, path, body) = requests.last().unwrap();
+        assert_eq!(path, "/channels/1/messages");
+        assert!(body.contains("Suppress delay set to 0ms (was 200ms)."));
+
+        // A bad value is explained and changes nothing
+        dispatch_event(
+            Arc::clone(&state),
+            command(2, "!tweetboat set suppress_delay soon"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state.suppress_delay(), 0);
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+
+        // Suppressions use the new delay right away
+        let mut message = mock::message(4, 1, "https://x.com/user/status/1");
+        message["embeds"] = json!([mock::embed(false)]);
+        dispatch_event(Arc::clone(&state), mock::create(message))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/4"), 1);
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "owners = [\"3\"]");
+        let mut command = mock::message(3, 1, "!tweetboat stats");
+        command["author"]["id"] = json!("3");
+
+        let link = "https://x.com/user/status/1";
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        // Edits and failed replies don't count
+        let edit = mock::message(1, 1, "https://x.com/user/status/2");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        mock.fail_next("POST", 403);
+        let link = "https://www.instagram.com/p/a/";
+        let failed = dispatch_event(Arc::clone(&state), mock::message_create(2, 1, link)).await;
+        assert!(failed.is_err());
+
+        dispatch_event(Arc::clone(&state), mock::create(command))
+            .await
+            .unwrap();
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains(r"`Tweet`: 1 fixes\n`Instagram Post`: 0 fixes"));
+    }
+
+    #[tokio::test]
+    async fn delete_original() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "delete_original = true");
+
+        let link = "https://x.com/user/status/1";
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1"), 1);
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+        let requests = mock.requests();
+        let (method, _, body) = &requests[0];
+        assert_eq!(method, "POST");
+        assert!(body.contains("<@2>: [`Tweet`](https://vxtwitter.com/user/status/1)"));
+        assert!(!body.contains("message_reference"));
+
+        // Deleting the message doesn't take the fix with it
+        let delete = json!({ "id": "1", "channel_id": "1" });
+        let delete = Event::MessageDelete(serde_json::from_value(delete).unwrap());
+        dispatch_event(Arc::clone(&state), delete).await.unwrap();
+        assert_eq!(mock.requests().len(), 2);
+
+        // Without the permission to delete it, the fix is taken down and the
+        // message gets a reply
+        mock.fail_next("DELETE", 403);
+        dispatch_event(Arc::clone(&state), mock::message_create(2, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1001"), 1);
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 3);
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains("message_reference"));
+        assert!(state
+            .replies
+            .read()
+            .unwrap()
+            .get_entry(Id::new(2))
+            .is_some());
+
+        // If the fix can't be posted, the message isn't deleted
+        mock.fail_next("POST", 403);
+        let failed = dispatch_event(Arc::clone(&state), mock::message_create(3, 1, link)).await;
+        assert!(failed.is_err());
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/3"), 0);
+    }
+
+    #[tokio::test]
+    async fn ignore_replies() {
+        let mock = MockDiscord::spawn().await;
+        let reply = |id| {
+            let mut message = mock::message(id, 1, "https://x.com/user/status/1");
+            message["type"] = json!(19);
+            message["referenced_message"] = mock::message(100, 1, "look at this");
+            mock::create(message)
+        };
+
+        let ignoring = state(&mock, "ignore_replies = true");
+        dispatch_event(Arc::clone(&ignoring), reply(1))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+
+        // Messages that aren't replies are still fixed
+        dispatch_event(
+            Arc::clone(&ignoring),
+            mock::message_create(2, 1, "https://x.com/user/status/1"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+
+        let fixing = state(&mock, "");
+        dispatch_event(Arc::clone(&fixing), reply(3)).await.unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+    }
+
+    #[tokio::test]
+    async fn dedup_window() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "dedup_window_secs = 60\ndedup_reaction = true");
+
+        // The same post from another host is still a repeat
+        for (id, link) in [
+            (1, "https://x.com/user/status/1"),
+            (2, "https://twitter.com/user/status/1?s=46"),
+        ] {
+            dispatch_event(Arc::clone(&state), mock::message_create(id, 1, link))
+                .await
+                .unwrap();
+        }
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+        assert_eq!(
+            mock.count("PUT", "/messages/2/reactions/%F0%9F%94%81/@me"),
+            1
+        );
+
+        // New links and other channels get fixed as usual
+        let link = "https://x.com/user/status/1 https://x.com/user/status/2";
+        dispatch_event(Arc::clone(&state), mock::message_create(3, 1, link))
+            .await
+            .unwrap();
+        let link = "https://x.com/user/status/1";
+        dispatch_event(Arc::clone(&state), mock::message_create(4, 2, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+        assert_eq!(mock.count("POST", "/channels/2/messages"), 1);
+
+        // A fix that didn't go out doesn't count
+        let link = "https://x.com/user/status/3";
+        mock.fail_next("POST", 403);
+        let failed = dispatch_event(Arc::clone(&state), mock::message_create(5, 1, link)).await;
+        assert!(failed.is_err());
+        dispatch_event(Arc::clone(&state), mock::message_create(6, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 4);
+    }
+
+    #[tokio::test]
+    async fn max_message_age() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "max_message_age_secs = 60");
+        let link = "https://x.com/user/status/1";
+
+        // Sent at the very start of Discord's epoch
+        let old = 1 << 22;
+        dispatch_event(Arc::clone(&state), mock::message_create(old, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let new = (millis - DISCORD_EPOCH_MILLIS) << 22;
+        dispatch_event(Arc::clone(&state), mock::message_create(new, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+    }
+
+    #[test]
+    fn ages() {
+        let now = UNIX_EPOCH + Duration::from_millis(DISCORD_EPOCH_MILLIS + 10_000);
+        let id = Id::<UserMarker>::new(4_000 << 22);
+        assert_eq!(snowflake_age(id, now), Duration::from_secs(6));
+
+        let joined = Timestamp::from_secs(1_700_000_000).unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_090);
+        assert_eq!(member_age(joined, now), Duration::from_secs(90));
+        let before = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        assert_eq!(member_age(joined, before), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn min_author_age() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "min_account_age_secs = 60\nmin_member_age_secs = 60");
+        let link = "https://x.com/user/status/1";
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let new_id = (now.as_millis() as u64 - DISCORD_EPOCH_MILLIS) << 22;
+        let joined_now = Timestamp::from_secs(now.as_secs() as i64).unwrap();
+        let create = |id, author: u64, joined_at: Option<Timestamp>| {
+            let mut message = mock::message(id, 1, link);
+            message["author"]["id"] = json!(author.to_string());
+            if let Some(joined_at) = joined_at {
+                message["member"] = json!({
+                    "deaf": false,
+                    "flags": 0,
+                    "joined_at": joined_at,
+                    "mute": false,
+                    "roles": [],
+                });
+            }
+            mock::create(message)
+        };
+
+        // A brand new account, or an old one that just joined
+        dispatch_event(Arc::clone(&state), create(1, new_id, None))
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&state), create(2, 2, Some(joined_now)))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+
+        // An old account that's been around, or that doesn't say when it joined
+        let joined_long_ago = Timestamp::from_secs(1_500_000_000).unwrap();
+        dispatch_event(Arc::clone(&state), create(3, 2, Some(joined_long_ago)))
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&state), create(4, 2, None))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+    }
+
+    #[tokio::test]
+    async fn own_reply() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "");
+        let link = "https://x.com/user/status/1";
+
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+
+        // The reply comes back through the gateway without the bot flag
+        let reply = mock::message_create(mock::FIRST_REPLY_ID, 1, link);
+        dispatch_event(Arc::clone(&state), reply).await.unwrap();
+
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+    }
+
+    #[tokio::test]
+    async fn keep_filtered_replies() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "keep_filtered_replies = true");
+        let link = "https://x.com/user/status/1";
+
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+
+        // Putting the link in code leaves the reply alone
+        let edit = mock::message(1, 1, "`https://x.com/user/status/1`");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 0);
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+
+        // Removing it still deletes the reply
+        let edit = mock::message(1, 1, "never mind");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+    }
+
+    #[tokio::test]
+    async fn topic_directives() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "topic_directives = true");
+        let link = "https://x.com/user/status/1";
+        let update = |topic: &str| {
+            let channel = json!({ "id": "1", "type": 0, "topic": topic });
+            Event::ChannelUpdate(Box::new(serde_json::from_value(channel).unwrap()))
+        };
+
+        dispatch_event(Arc::clone(&state), update("no bots [tweetboat:off]"))
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+
+        dispatch_event(Arc::clone(&state), update("bots welcome"))
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&state), mock::message_create(2, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+    }
+
+    #[tokio::test]
+    async fn recreate_deleted_reply() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "");
+
+        let link = "https://x.com/user/status/1";
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+
+        // A moderator deleted the reply, so editing it fails
+        mock.fail_next("PATCH", 404);
+        let edit = mock::message(1, 1, "https://x.com/user/status/2");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 1);
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+        assert_eq!(
+            state.replies.read().unwrap().get_entry(Id::new(1)),
+            Some(CacheEntry::Filled(Id::new(1001)))
+        );
+    }
+
+    #[tokio::test]
+    async fn suppress_concurrency() {
+        let mock = MockDiscord::with_latency(Duration::from_millis(20)).await;
+        let rest = mock.client();
+        let permits = Arc::new(Semaphore::new(2));
+
+        let tasks: Vec<_> = (1..=8)
+            .map(|id| {
+                suppress_embeds_deferred(
+                    &rest,
+                    &permits,
+                    0,
+                    MessageFlags::SUPPRESS_EMBEDS,
+                    Id::new(1),
+                    Id::new(id),
+                )
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(mock.requests().len(), 8);
+        assert_eq!(mock.max_in_flight(), 2);
+    }
+
+    #[tokio::test]
+    async fn classify_http_errors() {
+        let mock = MockDiscord::spawn().await;
+        let rest = mock.client();
+
+        for (status, class) in [
+            (429, ErrorClass::RateLimited),
+            (403, ErrorClass::Forbidden),
+            (404, ErrorClass::NotFound),
+            (503, ErrorClass::Transient),
+            (500, ErrorClass::Transient),
+            (400, ErrorClass::Other),
+        ] {
+            mock.fail_next("DELETE", status);
+            let error = rest
+                .delete_message(Id::new(1), Id::new(1))
+                .await
+                .unwrap_err();
+
+            assert_eq!(ErrorClass::of(&error), class, "status {status}");
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_transient_errors() {
+        let mock = MockDiscord::spawn().await;
+        let rest = mock.client();
+
+        // Transient failures are retried until the request goes through
+        mock.fail_next("DELETE", 500);
+        mock.fail_next("DELETE", 503);
+        retry_transient(|| rest.delete_message(Id::new(1), Id::new(1)).into_future())
+            .await
+            .unwrap();
+        assert_eq!(mock.count("DELETE", "/messages/1"), 3);
+
+        // Anything else gives up immediately
+        mock.fail_next("DELETE", 403);
+        retry_transient(|| rest.delete_message(Id::new(1), Id::new(2)).into_future())
+            .await
+            .unwrap_err();
+        assert_eq!(mock.count("DELETE", "/messages/2"), 1);
+    }
+
+    #[tokio::test]
+    async fn post_delay_cancelled_by_embed() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "post_delay_millis = 50\nsuppress_delay_millis = 0");
+
+        // An embed without media still gets fixed, but is suppressed
+        let link = "https://x.com/user/status/1";
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        let mut embeds = mock::message(1, 1, link);
+        embeds["embeds"] = json!([mock::embed(false)]);
+        dispatch_event(Arc::clone(&state), mock::update(embeds))
+            .await
+            .unwrap();
+
+        // A working embed during the delay cancels the reply
+        dispatch_event(Arc::clone(&state), mock::message_create(2, 1, link))
+            .await
+            .unwrap();
+        let mut embeds = mock::message(2, 1, link);
+        embeds["embeds"] = json!([mock::embed(true)]);
+        dispatch_event(Arc::clone(&state), mock::update(embeds))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1"), 1);
+        assert!(state
+            .replies
+            .read()
+            .unwrap()
+            .get_entry(Id::new(2))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn reply_ttl() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "");
+        let ttl = Duration::from_millis(20);
+
+        let link = "https://x.com/user/status/1";
+        for id in [1, 2] {
+            dispatch_event(Arc::clone(&state), mock::message_create(id, 1, link))
+                .await
+                .unwrap();
+        }
+
+        // The first reply expires and is forgotten
+        let expired = delete_reply_deferred(Arc::clone(&state), ttl, Id::new(1), Id::new(1));
+        expired.await.unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+        assert_eq!(state.replies.read().unwrap().get_entry(Id::new(1)), None);
+
+        // Editing the second keeps it around
+        let kept = delete_reply_deferred(Arc::clone(&state), ttl, Id::new(1), Id::new(2));
+        let edit = mock::message(2, 1, "https://x.com/user/status/2");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        kept.await.unwrap();
+
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1001"), 1);
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1001"), 0);
+        assert!(state
+            .replies
+            .read()
+            .unwrap()
+            .get_entry(Id::new(2))
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn reply_in_new_thread() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "reply_in_new_thread = true");
+
+        let link = "https://x.com/user/status/1";
+        dispatch_event(Arc::clone(&state), mock::message_create(10, 1, link))
+            .await
+            .unwrap();
+
+        // The reply goes into the thread, which shares the source's ID
+        assert_eq!(mock.count("POST", "/channels/1/messages/10/threads"), 1);
+        assert_eq!(mock.count("POST", "/channels/10/messages"), 1);
+
+        let edit = mock::message(10, 1, "https://x.com/user/status/2");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("PATCH", "/channels/10/messages/1000"), 1);
+
+        // Without permission to create threads it replies normally
+        mock.fail_next("POST", 403);
+        dispatch_event(Arc::clone(&state), mock::message_create(11, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages/11/threads"), 1);
+        assert_eq!(mock.count("POST", "/channels/11/messages"), 0);
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+
+        // If the thread is made but the reply can't go in it, the thread is
+        // deleted and it replies normally
+        mock.fail_next_at("POST", "/channels/12/messages", 403);
+        dispatch_event(Arc::clone(&state), mock::message_create(12, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages/12/threads"), 1);
+        assert_eq!(mock.count("POST", "/channels/12/messages"), 1);
+        assert_eq!(mock.count("DELETE", "/channels/12"), 1);
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+        let entry = state.replies.read().unwrap().get_entry(Id::new(12));
+        assert!(matches!(entry, Some(CacheEntry::Filled(_))));
+    }
+
+    #[test]
+    fn jump_urls() {
+        assert_eq!(
+            jump_url(Some(Id::new(7)), Id::new(1), Id::new(10)).as_deref(),
+            Some("https://discord.com/channels/7/1/10")
+        );
+        assert_eq!(jump_url(None, Id::new(1), Id::new(10)), None);
+    }
+
+    #[tokio::test]
+    async fn mirror_channel() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "mirror_channel = 50");
+
+        let mut message = mock::message(10, 1, "https://x.com/user/status/1");
+        message["guild_id"] = json!("7");
+        dispatch_event(Arc::clone(&state), mock::create(message))
+            .await
+            .unwrap();
+
+        // Nothing is posted inline, and the mirrored fix links back
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+        assert_eq!(mock.count("POST", "/channels/50/messages"), 1);
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains("https://discord.com/channels/7/1/10 "));
+        assert!(!body.contains("message_reference"));
+
+        let mut edit = mock::message(10, 1, "https://x.com/user/status/2");
+        edit["guild_id"] = json!("7");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("PATCH", "/channels/50/messages/1000"), 1);
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains("https://discord.com/channels/7/1/10 "));
+
+        let delete = json!({ "id": "10", "channel_id": "1" });
+        let delete = Event::MessageDelete(serde_json::from_value(delete).unwrap());
+        dispatch_event(Arc::clone(&state), delete).await.unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/50/messages/1000"), 1);
+    }
+
+    #[tokio::test]
+    async fn mirror_without_jump_link() {
+        let mock = MockDiscord::spawn().await;
+        let unlinked = state(&mock, "mirror_channel = 50\ninclude_jump_link = false");
+
+        let mut message = mock::message(10, 1, "https://x.com/user/status/1");
+        message["guild_id"] = json!("7");
+        dispatch_event(Arc::clone(&unlinked), mock::create(message))
+            .await
+            .unwrap();
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(!body.contains("https://discord.com/channels/"));
+
+        // Messages in DMs have nowhere to jump to, even with the link turned on
+        let linked = state(&mock, "mirror_channel = 50");
+        let message = mock::message(11, 1, "https://x.com/user/status/1");
+        dispatch_event(Arc::clone(&linked), mock::create(message))
+            .await
+            .unwrap();
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains("vxtwitter.com"));
+        assert!(!body.contains("https://discord.com/channels/"));
+    }
+
+    #[tokio::test]
+    async fn reply_overflow() {
+        let links: Vec<_> = (1..=60)
+            .map(|id| format!("https://x.com/user/status/{id}"))
+            .collect();
+        let content = links.join(" ");
+
+        // Cut short by default
+        let mock = MockDiscord::spawn().await;
+        let first = state(&mock, "");
+        dispatch_event(Arc::clone(&first), mock::message_create(1, 1, &content))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert!(body["content"].as_str().unwrap().len() <= 2000);
+
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "reply_overflow = \"split\"");
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, &content))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+        assert_eq!(
+            state.replies.read().unwrap().overflow(Id::new(1)),
+            [Id::new(1001)]
+        );
+
+        // Editing down to one link drops the extra message
+        let edit = mock::message(1, 1, "https://x.com/user/status/1");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 1);
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1001"), 1);
+        assert!(state
+            .replies
+            .read()
+            .unwrap()
+            .overflow(Id::new(1))
+            .is_empty());
+
+        // And editing back up sends it again
+        let edit = mock::message(1, 1, &content);
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 3);
+        assert_eq!(
+            state.replies.read().unwrap().overflow(Id::new(1)),
+            [Id::new(1002)]
+        );
+
+        let delete = json!({ "id": "1", "channel_id": "1" });
+        let delete = Event::MessageDelete(serde_json::from_value(delete).unwrap());
+        dispatch_event(Arc::clone(&state), delete).await.unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1002"), 1);
+    }
+
+    #[test]
+    fn preloaded_replies() {
+        let reply = |id: u64, author: &str, reference: serde_json::Value| -> Message {
+            let mut message = mock::message(id, 1, "");
+            message["author"]["id"] = json!(author);
+            message["message_reference"] = reference;
+            serde_json::from_value(message).unwrap()
+        };
+
+        let history = [
+            reply(20, "1", json!({ "message_id": "10", "channel_id": "1" })),
+            // Someone else's reply
+            reply(21, "2", json!({ "message_id": "11", "channel_id": "1" })),
+            // A reference to another channel
+            reply(22, "1", json!({ "message_id": "12", "channel_id": "5" })),
+            // No channel is the same channel
+            reply(23, "1", json!({ "message_id": "13" })),
+            reply(24, "1", json!(null)),
+        ];
+
+        assert_eq!(
+            own_replies(Id::new(1), Id::new(1), &history),
+            [(Id::new(10), Id::new(20)), (Id::new(13), Id::new(23))]
+        );
+    }
+
+    #[tokio::test]
+    async fn spawned_dispatch() {
+        let mock = MockDiscord::with_latency(Duration::from_millis(100)).await;
+        let state = state(&mock, "");
+        let link = "https://x.com/user/status/1";
+
+        // Replying to one message doesn't hold up another
+        let first = spawn_dispatch(&state, mock::message_create(1, 1, link));
+        let second = spawn_dispatch(&state, mock::message_create(2, 1, link));
+        first.await.unwrap();
+        second.await.unwrap();
+        assert_eq!(mock.max_in_flight(), 2);
+
+        // But an edit right after its create waits for the reply to be sent
+        let create = spawn_dispatch(&state, mock::message_create(3, 1, link));
+        let edit = mock::message(3, 1, "https://x.com/user/status/2");
+        let edit = spawn_dispatch(&state, mock::update(edit));
+        create.await.unwrap();
+        edit.await.unwrap();
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1002"), 1);
+    }
+
+    #[tokio::test]
+    async fn traced_events() {
+        let mock = MockDiscord::spawn().await;
+        let fields = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let link = "https://x.com/user/status/1";
+
+        // Nothing is traced unless it's turned on
+        let untraced = state(&mock, "");
+        spawn_dispatch(&untraced, mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(fields.get("decision"), None);
+
+        let traced = state(&mock, "trace_events = true");
+        spawn_dispatch(&traced, mock::message_create(2, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(fields.get("kind").as_deref(), Some("MessageCreate"));
+        assert_eq!(fields.get("message").as_deref(), Some("2"));
+        assert_eq!(fields.get("channel").as_deref(), Some("1"));
+        assert_eq!(fields.get("matched").as_deref(), Some("true"));
+        assert_eq!(fields.get("decision").as_deref(), Some("reply"));
+
+        spawn_dispatch(&traced, mock::message_create(3, 1, "no links"))
+            .await
+            .unwrap();
+        assert_eq!(fields.get("message").as_deref(), Some("3"));
+        assert_eq!(fields.get("matched").as_deref(), Some("false"));
+        assert_eq!(fields.get("decision").as_deref(), Some("no links"));
+    }
+
+    #[tokio::test]
+    async fn edit_out_grace() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "edit_out_grace_millis = 50");
+        let link = "https://x.com/user/status/1";
+
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+
+        // The link is back before the grace is up, so the reply stays
+        for content in ["https:/x.com/user/status/1", link] {
+            let edit = mock::message(1, 1, content);
+            dispatch_event(Arc::clone(&state), mock::update(edit))
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 1);
+
+        let edit = mock::message(1, 1, "no links");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+        assert_eq!(state.replies.read().unwrap().get_entry(Id::new(1)), None);
+    }
+
+    /// Builds the event for `user_id` clicking a button on the reply to message
+    /// 1, which was sent by the mock as message 1000.
+    fn click(custom_id: &str, user_id: u64) -> Event {
+        let interaction = json!({
+            "id": "5",
+            "application_id": "9",
+            "type": 3,
+            "token": "token",
+            "data": { "custom_id": custom_id, "component_type": 2 },
+            "message": mock::message(1000, 1, "[`Tweet`](https://vxtwitter.com/user/status/1)"),
+            "user": { "id": user_id.to_string(), "username": "user", "discriminator": "0" },
+        });
+        Event::InteractionCreate(Box::new(InteractionCreate(
+            serde_json::from_value(interaction).unwrap(),
+        )))
+    }
+
+    #[test]
+    fn delete_buttons() {
+        assert_eq!(
+            parse_delete_button("delete:1:2"),
+            Some((Id::new(1), Id::new(2)))
+        );
+        assert_eq!(parse_delete_button("delete:1"), None);
+        assert_eq!(parse_delete_button("delete:0:2"), None);
+        assert_eq!(parse_delete_button("switch:1:2"), None);
+    }
+
+    #[tokio::test]
+    async fn reply_buttons() {
+        let mock = MockDiscord::spawn().await;
+        let buttoned = state(&mock, "reply_buttons = true");
+        let link = "https://x.com/user/status/1";
+
+        // The button knows whose message the reply is to
+        dispatch_event(Arc::clone(&buttoned), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains(r#""custom_id":"delete:1:2""#));
+
+        // Someone else is turned away, and only they see it
+        dispatch_event(Arc::clone(&buttoned), click("delete:1:2", 3))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+        let requests = mock.requests();
+        let (_, path, body) = requests.last().unwrap();
+        assert_eq!(path, "/interactions/5/token/callback");
+        assert!(body.contains(r#""flags":64"#));
+
+        // But the author can delete it
+        dispatch_event(Arc::clone(&buttoned), click("delete:1:2", 2))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/interactions/5/token/callback"), 2);
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+        let entry = buttoned.replies.read().unwrap().get_entry(Id::new(1));
+        assert_eq!(entry, Some(CacheEntry::Pending));
+
+        // Clicks on buttons that aren't ours are ignored
+        let requests = mock.requests().len();
+        dispatch_event(Arc::clone(&buttoned), click("vote:up", 2))
+            .await
+            .unwrap();
+        assert_eq!(mock.requests().len(), requests);
+
+        // No buttons unless they're turned on
+        let plain = state(&mock, "");
+        dispatch_event(Arc::clone(&plain), mock::message_create(2, 1, link))
+            .await
+            .unwrap();
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(!body.contains("custom_id"));
+    }
+
+    #[tokio::test]
+    async fn edit_out_in_flight() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "");
+        let link = "https://x.com/user/status/1";
+        let file = |source| {
+            let mut replies = state.replies.write().unwrap();
+            replies.file_pending(Id::new(source)).unwrap()
+        };
+
+        // The links are edited out while the reply is still being sent
+        let token = file(1);
+        dispatch_event(
+            Arc::clone(&state),
+            mock::update(mock::message(1, 1, "gone")),
+        )
+        .await
+        .unwrap();
+        assert!(mock.requests().is_empty());
+        state.replies.write().unwrap().insert(token, Id::new(1000));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+        assert_eq!(state.replies.read().unwrap().get_entry(Id::new(1)), None);
+
+        // And back in before it's there, so it's edited instead
+        let token = file(2);
+        dispatch_event(
+            Arc::clone(&state),
+            mock::update(mock::message(2, 1, "gone")),
+        )
+        .await
+        .unwrap();
+        dispatch_event(Arc::clone(&state), mock::update(mock::message(2, 1, link)))
+            .await
+            .unwrap();
+        state.replies.write().unwrap().insert(token, Id::new(1001));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1001"), 0);
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1001"), 1);
+    }
+
+    #[tokio::test]
+    async fn suppress_failure() {
+        let link = "https://x.com/user/status/1";
+        let create = |id| {
+            let mut message = mock::message(id, 1, link);
+            message["embeds"] = json!([mock::embed(false)]);
+            mock::create(message)
+        };
+
+        // Left alone by default, so there are two embeds
+        let mock = MockDiscord::spawn().await;
+        let keeping = state(&mock, "suppress_delay_millis = 0");
+        mock.fail_next("PATCH", 403);
+        dispatch_event(Arc::clone(&keeping), create(1))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1"), 1);
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+
+        let mock = MockDiscord::spawn().await;
+        let reacting = state(
+            &mock,
+            "on_suppress_failure = \"react\"\nsuppress_delay_millis = 0",
+        );
+        mock.fail_next("PATCH", 403);
+        dispatch_event(Arc::clone(&reacting), create(1))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+        assert_eq!(
+            mock.count("PUT", "/channels/1/messages/1/reactions/%F0%9F%94%97/@me"),
+            1
+        );
+        let entry = reacting.replies.read().unwrap().get_entry(Id::new(1));
+        assert_eq!(entry, Some(CacheEntry::Pending));
+
+        // Edits don't bring back a reply that was removed
+        let edit = mock::message(1, 1, "https://x.com/user/status/2");
+        dispatch_event(Arc::clone(&reacting), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+
+        // Suppressed fine, so the reply stays
+        dispatch_event(Arc::clone(&reacting), create(2))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1001"), 0);
+
+        // A reply still waiting out its delay is never sent
+        let mock = MockDiscord::spawn().await;
+        let deleting = state(
+            &mock,
+            "on_suppress_failure = \"delete\"\npost_delay_millis = 50\nsuppress_delay_millis = 0",
+        );
+        mock.fail_next("PATCH", 403);
+        dispatch_event(Arc::clone(&deleting), create(1))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+        assert_eq!(mock.count("PUT", "/reactions/%F0%9F%94%97/@me"), 0);
+    }
+
+    #[tokio::test]
+    async fn empty_rewrite() {
+        let link = "https://x.com/user/status/1";
+        let create = || mock::message_create(1, 1, link);
+        let edit = || mock::update(mock::message(1, 1, "no links"));
+
+        let mock = MockDiscord::spawn().await;
+        let deleting = state(&mock, r#"on_empty_rewrite = "delete""#);
+        dispatch_event(Arc::clone(&deleting), create())
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&deleting), edit()).await.unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 1);
+        assert_eq!(deleting.replies.read().unwrap().get_entry(Id::new(1)), None);
+
+        let mock = MockDiscord::spawn().await;
+        let keeping = state(&mock, r#"on_empty_rewrite = "keep""#);
+        dispatch_event(Arc::clone(&keeping), create())
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&keeping), edit()).await.unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 0);
+        let entry = keeping.replies.read().unwrap().get_entry(Id::new(1));
+        assert!(entry.is_some());
+
+        let mock = MockDiscord::spawn().await;
+        let clearing = state(&mock, r#"on_empty_rewrite = "clear_content""#);
+        dispatch_event(Arc::clone(&clearing), create())
+            .await
+            .unwrap();
+        dispatch_event(Arc::clone(&clearing), edit()).await.unwrap();
+        assert_eq!(mock.count("DELETE", "/channels/1/messages/1000"), 0);
+        let requests = mock.requests();
+        let (method, _, body) = requests.last().unwrap();
+        assert_eq!(method, "PATCH");
+        assert!(body.contains("*Links removed*"));
+
+        // The reply is still tracked, so bringing the links back restores it
+        let edit = mock::message(1, 1, link);
+        dispatch_event(Arc::clone(&clearing), mock::update(edit))
+            .await
+            .unwrap();
+        let requests = mock.requests();
+        let (_, path, body) = requests.last().unwrap();
+        assert_eq!(path, "/channels/1/messages/1000");
+        assert!(body.contains("vxtwitter.com"));
+    }
+
+    #[tokio::test]
+    async fn channel_passes() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(
+            &mock,
+            r#"
+            [[channel]]
+            id = 1
+            enabled_passes = ["Instagram Post"]
+            "#,
+        );
+        let content = "https://x.com/user/status/1 https://www.instagram.com/p/a/";
+
+        // Only Instagram runs in channel 1, and everything does in channel 2
+        for (id, channel) in [(1, 1), (2, 2)] {
+            dispatch_event(
+                Arc::clone(&state),
+                mock::message_create(id, channel, content),
+            )
+            .await
+            .unwrap();
+        }
+
+        let requests = mock.requests();
+        let bodies: Vec<_> = requests.iter().map(|(_, _, body)| body).collect();
+        assert!(!bodies[0].contains("vxtwitter"));
+        assert!(bodies[0].contains("ddinstagram"));
+        assert!(bodies[1].contains("vxtwitter"));
+        assert!(bodies[1].contains("ddinstagram"));
+
+        // A channel with none of its links enabled gets no reply
+        let tweet = mock::message_create(3, 1, "https://x.com/user/status/1");
+        dispatch_event(Arc::clone(&state), tweet).await.unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+    }
+
+    #[tokio::test]
+    async fn global_api_budget() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(
+            &mock,
+            "global_api_budget = 4\ndedup_window_secs = 60\ndedup_reaction = true",
+        );
+        let link = "https://x.com/user/status/1";
+
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+
+        // Reactions stop with a quarter of the budget left...
+        for id in 2..=4 {
+            dispatch_event(Arc::clone(&state), mock::message_create(id, 1, link))
+                .await
+                .unwrap();
+        }
+        assert_eq!(mock.count("PUT", "/reactions/%F0%9F%94%81/@me"), 2);
+
+        // ...which edits to replies still get, until it runs out
+        let edit = mock::message(1, 1, "https://x.com/user/status/2");
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 1);
+
+        let event = mock::message_create(5, 1, "https://x.com/user/status/5");
+        dispatch_event(Arc::clone(&state), event).await.unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+        assert!(state
+            .replies
+            .read()
+            .unwrap()
+            .get_entry(Id::new(5))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn budget_per_call() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "global_api_budget = 3\nedit_coalesce_millis = 50");
+        let link = "https://x.com/user/status/1";
+
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+
+        // A burst of edits makes one call, so it's charged for one
+        for status in 2..=4 {
+            let edit = mock::message(1, 1, &format!("https://x.com/user/status/{status}"));
+            dispatch_event(Arc::clone(&state), mock::update(edit))
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 1);
+
+        // Anyone can click the delete button, but only its author is charged
+        for _ in 0..3 {
+            dispatch_event(Arc::clone(&state), click("delete:1:2", 3))
+                .await
+                .unwrap();
+        }
+        let event = mock::message_create(2, 1, "https://x.com/user/status/5");
+        dispatch_event(Arc::clone(&state), event).await.unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+    }
+
+    #[tokio::test]
+    async fn edit_coalescing() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "edit_coalesce_millis = 50");
+
+        let link = "https://x.com/user/status/1";
+        dispatch_event(Arc::clone(&state), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+
+        for status in 2..=4 {
+            let edit = mock::message(1, 1, &format!("https://x.com/user/status/{status}"));
+            dispatch_event(Arc::clone(&state), mock::update(edit))
+                .await
+                .unwrap();
+        }
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 0);
+
+        // Only the latest content is sent
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 1);
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains("/user/status/4"));
+    }
+
+    #[tokio::test]
+    async fn quiet_hours() {
+        // Message 1 was sent at midnight UTC
+        let link = "https://x.com/user/status/1";
+        let mock = MockDiscord::spawn().await;
+        let quiet = state(&mock, r#"quiet_hours = { start = "23:00", end = "01:00" }"#);
+        dispatch_event(Arc::clone(&quiet), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+
+        // An hour ahead of UTC, it's already over
+        let offset = r#"quiet_hours = { start = "23:00", end = "01:00", utc_offset_minutes = 60 }"#;
+        let awake = state(&mock, offset);
+        dispatch_event(Arc::clone(&awake), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_reply() {
+        let link = "https://x.com/user/status/1";
+
+        // Tried again once Discord's wait is up
+        let mock = MockDiscord::spawn().await;
+        let retrying = state(&mock, "");
+        mock.fail_next("POST", 429);
+        dispatch_event(Arc::clone(&retrying), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 2);
+        let entry = retrying.replies.read().unwrap().get_entry(Id::new(1));
+        assert_eq!(entry, Some(CacheEntry::Filled(Id::new(1001))));
+
+        // Out of retries, the pending entry is dropped
+        let mock = MockDiscord::spawn().await;
+        let giving_up = state(&mock, "reply_rate_limit_retries = 0");
+        mock.fail_next("POST", 429);
+        let dispatch = dispatch_event(Arc::clone(&giving_up), mock::message_create(1, 1, link));
+        assert!(dispatch.await.is_err());
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 1);
+        let entry = giving_up.replies.read().unwrap().get_entry(Id::new(1));
+        assert_eq!(entry, None);
+
+        // So is a reply that was sent but can't be read back
+        let mock = MockDiscord::spawn().await;
+        let unreadable = state(&mock, "");
+        mock.fail_next("POST", 200);
+        let dispatch = dispatch_event(Arc::clone(&unreadable), mock::message_create(1, 1, link));
+        assert!(dispatch.await.is_err());
+        let entry = unreadable.replies.read().unwrap().get_entry(Id::new(1));
+        assert_eq!(entry, None);
+
+        // Replies in a new thread are tried again too
+        let mock = MockDiscord::spawn().await;
+        let threaded = state(&mock, "reply_in_new_thread = true");
+        mock.fail_next_at("POST", "/threads", 429);
+        mock.fail_next_at("POST", "/channels/10/messages", 429);
+        dispatch_event(Arc::clone(&threaded), mock::message_create(10, 1, link))
+            .await
+            .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages/10/threads"), 2);
+        assert_eq!(mock.count("POST", "/channels/10/messages"), 2);
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 0);
+
+        // And so are the messages after the first of a split reply
+        let mock = MockDiscord::spawn().await;
+        let split = state(&mock, "reply_overflow = \"split\"");
+        dispatch_event(Arc::clone(&split), mock::message_create(1, 1, link))
+            .await
+            .unwrap();
+        let links: Vec<_> = (1..=60)
+            .map(|id| format!("https://x.com/user/status/{id}"))
+            .collect();
+        mock.fail_next("POST", 429);
+        dispatch_event(
+            Arc::clone(&split),
+            mock::update(mock::message(1, 1, &links.join(" "))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(mock.count("POST", "/channels/1/messages"), 3);
+        assert_eq!(
+            split.replies.read().unwrap().overflow(Id::new(1)),
+            [Id::new(1002)]
+        );
+    }
+
+    #[tokio::test]
+    async fn edit_while_pending() {
+        let mock = MockDiscord::spawn().await;
+        let state = state(&mock, "suppress_delay_millis = 0");
+
+        // The reply is still being sent when the source is edited and unfurled
+        let token = state.replies.write().unwrap().file_pending(Id::new(1));
+        let token = token.unwrap();
+        let mut edit = mock::message(1, 1, "https://x.com/user/status/2");
+        edit["embeds"] = json!([mock::embed(false)]);
+        dispatch_event(Arc::clone(&state), mock::update(edit))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1"), 1);
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 0);
+
+        // Once it's sent, it's brought up to date
+        state.replies.write().unwrap().insert(token, Id::new(1000));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(mock.count("PATCH", "/channels/1/messages/1000"), 1);
+        let requests = mock.requests();
+        let (_, _, body) = requests.last().unwrap();
+        assert!(body.contains("/user/status/2"));
+    }
+}
@@ -0,0 +1,277 @@
+//! A fake Discord REST API for exercising [dispatch_event] in tests.
+//!
+//! The mock listens on a local port and a [Client] is pointed at it through
+//! twilight's proxy support. Every request is recorded, message creates and
+//! edits are answered with a minimal message, thread creates with a minimal
+//! thread, and everything else gets an empty `204`. Failures can be queued up with [MockDiscord::fail_next].
+//!
+//! [dispatch_event]: crate::dispatch_event
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use twilight_http::Client;
+use twilight_model::gateway::event::Event;
+use twilight_model::gateway::payload::incoming::{MessageCreate, MessageUpdate};
+
+/// The ID given to the first reply the mock sends, incremented for each one
+/// after that.
+pub const FIRST_REPLY_ID: u64 = 1000;
+
+/// A request received by the mock, as `(method, path, body)`. The path has the
+/// `/api/v10` prefix stripped.
+pub type Request = (String, String, String);
+
+pub struct MockDiscord {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+/// State shared between the mock and its connection tasks.
+#[derive(Default)]
+struct Shared {
+    requests: Mutex<Vec<Request>>,
+    failures: Mutex<Vec<(String, String, u16)>>,
+    latency: Duration,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl MockDiscord {
+    pub async fn spawn() -> Self {
+        Self::with_latency(Duration::ZERO).await
+    }
+
+    /// Spawns a mock that waits for `latency` before answering each request.
+    pub async fn with_latency(latency: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shared = Arc::new(Shared {
+            latency,
+            ..Shared::default()
+        });
+
+        let conn_shared = Arc::clone(&shared);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, Arc::clone(&conn_shared)));
+            }
+        });
+
+        Self { addr, shared }
+    }
+
+    /// Creates a client that sends all of its requests to this mock.
+    pub fn client(&self) -> Client {
+        Client::builder()
+            .proxy(self.addr.to_string(), true)
+            .ratelimiter(None)
+            .build()
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    /// Makes the next request with the given method fail with `status`. Calls
+    /// stack, and each queued failure is used up by one request. A status
+    /// that isn't an error answers with a body that isn't what was asked for.
+    pub fn fail_next(&self, method: &str, status: u16) {
+        self.fail_next_at(method, "", status);
+    }
+
+    /// Like [fail_next], but only for requests to paths ending in `path`.
+    ///
+    /// [fail_next]: MockDiscord::fail_next
+    pub fn fail_next_at(&self, method: &str, path: &str, status: u16) {
+        let mut failures = self.shared.failures.lock().unwrap();
+        failures.push((method.to_owned(), path.to_owned(), status));
+    }
+
+    /// The most requests that were ever being answered at the same time.
+    pub fn max_in_flight(&self) -> usize {
+        self.shared.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Counts the requests made with the given method to paths ending in `path`.
+    pub fn count(&self, method: &str, path: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|(m, p, _)| m == method && p.ends_with(path))
+            .count()
+    }
+}
+
+async fn serve(mut stream: TcpStream, shared: Arc<Shared>) {
+    let mut buf = Vec::new();
+
+    loop {
+        // HTTP/1.1 keeps the connection alive, so requests are read back to back
+        let head_len = loop {
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+
+            let mut chunk = [0; 4096];
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        };
+
+        let head = String::from_utf8_lossy(&buf[..head_len]).into_owned();
+        let body_len = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+
+        while buf.len() < head_len + body_len {
+            let mut chunk = [0; 4096];
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+
+        let body = String::from_utf8_lossy(&buf[head_len..head_len + body_len]).into_owned();
+        buf.drain(..head_len + body_len);
+
+        let mut request_line = head.split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_owned();
+        let path = request_line.next().unwrap_or_default();
+        let path = path.strip_prefix("/api/v10").unwrap_or(path).to_owned();
+
+        let in_flight = shared.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        shared.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        tokio::time::sleep(shared.latency).await;
+        shared.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let response = {
+            let mut log = shared.requests.lock().unwrap();
+            let sent = log
+                .iter()
+                .filter(|(m, p, _)| m == "POST" && p.ends_with("/messages"))
+                .count();
+            let reply_id = FIRST_REPLY_ID + sent as u64;
+            log.push((method.clone(), path.clone(), body));
+
+            let mut failures = shared.failures.lock().unwrap();
+            let failure = failures
+                .iter()
+                .position(|(m, p, _)| *m == method && path.ends_with(p.as_str()));
+            let failure = failure.map(|idx| failures.remove(idx).2);
+
+            match (failure, method.as_str()) {
+                (Some(status), _) => {
+                    let error = match status {
+                        429 => json!({ "message": "You are being rate limited.", "retry_after": 0.01, "global": false }),
+                        _ => json!({ "message": "Mock failure", "code": 0 }),
+                    }
+                    .to_string();
+
+                    format!(
+                        "HTTP/1.1 {status} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{error}",
+                        error.len()
+                    )
+                }
+                // Threads started from a message share its ID
+                (None, "POST") if path.ends_with("/threads") => {
+                    let thread_id = path.split('/').nth(4).unwrap_or("1");
+                    let thread = json!({ "id": thread_id, "type": 11 }).to_string();
+
+                    format!(
+                        "HTTP/1.1 201 Created\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{thread}",
+                        thread.len()
+                    )
+                }
+                (None, "POST" | "PATCH") if path.contains("/messages") => {
+                    let channel_id = path.split('/').nth(2).unwrap_or("1");
+                    let message_id = path
+                        .split('/')
+                        .nth(4)
+                        .map_or_else(|| reply_id.to_string(), str::to_owned);
+                    let reply = json!({
+                        "id": message_id,
+                        "channel_id": channel_id,
+                        "author": { "id": "1", "username": "tweetboat", "discriminator": "0", "bot": true },
+                        "content": "",
+                        "attachments": [],
+                        "embeds": [],
+                        "mention_everyone": false,
+                        "mention_roles": [],
+                        "mentions": [],
+                        "pinned": false,
+                        "timestamp": "2024-04-05T00:00:00.000000+00:00",
+                        "tts": false,
+                        "type": 0,
+                    })
+                    .to_string();
+
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{reply}",
+                        reply.len()
+                    )
+                }
+                _ => "HTTP/1.1 204 No Content\r\n\r\n".to_owned(),
+            }
+        };
+
+        if stream.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Builds the JSON for a message from a regular user, for tests to tweak
+/// before turning it into an event.
+pub fn message(id: u64, channel_id: u64, content: &str) -> Value {
+    json!({
+        "id": id.to_string(),
+        "channel_id": channel_id.to_string(),
+        "author": { "id": "2", "username": "user", "discriminator": "0" },
+        "content": content,
+        "attachments": [],
+        "embeds": [],
+        "mention_everyone": false,
+        "mention_roles": [],
+        "mentions": [],
+        "pinned": false,
+        "timestamp": "2024-04-05T00:00:00.000000+00:00",
+        "tts": false,
+        "type": 0,
+    })
+}
+
+/// Builds the JSON for an embed the unfurler attached, with or without media.
+pub fn embed(media: bool) -> Value {
+    let mut embed = json!({ "type": "rich", "title": "A post" });
+    if media {
+        embed["image"] = json!({ "url": "https://pbs.twimg.com/media/a.jpg" });
+    }
+
+    embed
+}
+
+pub fn create(message: Value) -> Event {
+    let message = serde_json::from_value(message).unwrap();
+    Event::MessageCreate(Box::new(MessageCreate(message)))
+}
+
+/// Builds a [MessageUpdate] event. Only `id` and `channel_id` are required.
+pub fn update(message: Value) -> Event {
+    let message: MessageUpdate = serde_json::from_value(message).unwrap();
+    Event::MessageUpdate(Box::new(message))
+}
+
+/// Builds a [MessageCreate] event for a message from a regular user.
+pub fn message_create(id: u64, channel_id: u64, content: &str) -> Event {
+    create(message(id, channel_id, content))
+}
@@ -1,4 +1,11 @@
-use std::{collections::HashMap, fmt::Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    borrow::{Borrow, Cow},
+    cmp::Reverse,
+    collections::HashMap,
+    fmt::Write,
+    ops::Range,
+};
 
 use regex::Regex;
 use serde::{Deserialize, Deserializer};
@@ -6,10 +13,251 @@ use serde::{Deserialize, Deserializer};
 #[derive(Deserialize)]
 pub struct Pass {
     pub label: String,
-    #[serde(deserialize_with = "pass_regex")]
-    pub regex: Regex,
-    pub stem: String,
+    /// Built from the `kind`, `regex`, `flags`, `any_scheme`, `stem`, and
+    /// `replacement` keys.
+    #[serde(flatten, deserialize_with = "pass_kind")]
+    pub kind: PassKind,
     pub keep_query: Option<Vec<String>>,
+    /// Whether to append the ID at the end of the link's path to the label.
+    #[serde(default)]
+    pub show_id: bool,
+    /// Whether to skip links straight to media, which mirrors often can't
+    /// handle. See [is_media_path].
+    #[serde(default)]
+    pub skip_media: bool,
+    /// If set, links whose path has fewer non-empty segments than this are
+    /// skipped, like links to a profile instead of a post.
+    pub min_path_segments: Option<usize>,
+    /// Whether the pass's links in a reply start with its label, like
+    /// `Tweet fixed: `, to say what kind of links they are.
+    #[serde(default)]
+    pub link_type_label: bool,
+    /// Where this pass's links go in the reply: higher priorities come first,
+    /// and passes with equal priorities keep their config order. This only
+    /// affects the order of the output.
+    #[serde(default)]
+    pub priority: i32,
+    /// Stems to use instead of `stem` for paths matching a pattern.
+    #[serde(default, rename = "route")]
+    pub routes: Vec<StemRoute>,
+    /// Which stem to use when several could rewrite a link.
+    #[serde(default)]
+    pub stem_selection: StemSelection,
+    /// The number of replies this pass went into, as [recorded].
+    ///
+    /// [recorded]: Pass::record_fires
+    #[serde(skip)]
+    fires: AtomicU64,
+}
+
+/// What a pass does with what its regex matches.
+pub enum PassKind {
+    /// Rewrite links, replacing the part `regex` matched with the `stem`.
+    Link { regex: Regex, stem: Stem },
+    /// Replace everything `regex` matches with `replacement`, as in
+    /// [Regex::replace_all], and reply with the whole message.
+    Substitute { regex: Regex, replacement: String },
+}
+
+impl PassKind {
+    pub fn regex(&self) -> &Regex {
+        match self {
+            Self::Link { regex, .. } | Self::Substitute { regex, .. } => regex,
+        }
+    }
+
+    /// The stem links are rewritten to, if this is a link pass.
+    pub fn stem(&self) -> Option<&Stem> {
+        match self {
+            Self::Link { stem, .. } => Some(stem),
+            Self::Substitute { .. } => None,
+        }
+    }
+
+    /// Like [stem], but mutable.
+    ///
+    /// [stem]: PassKind::stem
+    pub fn stem_mut(&mut self) -> Option<&mut Stem> {
+        match self {
+            Self::Link { stem, .. } => Some(stem),
+            Self::Substitute { .. } => None,
+        }
+    }
+}
+
+/// The stem links are rewritten to: either one for every link, or a table from
+/// the host a link was on to its stem, with a `default` entry for other hosts.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Stem {
+    Single(String),
+    ByHost {
+        default: String,
+        #[serde(flatten)]
+        hosts: HashMap<String, String>,
+    },
+}
+
+impl Stem {
+    /// The stem for links on `host`.
+    pub fn for_host(&self, host: &str) -> &str {
+        match self {
+            Self::Single(stem) => stem,
+            Self::ByHost { default, hosts } => hosts.get(host).unwrap_or(default),
+        }
+    }
+
+    /// Every stem in the table, including the default.
+    pub fn stems(&self) -> impl Iterator<Item = &String> {
+        let (first, hosts) = match self {
+            Self::Single(stem) => (stem, None),
+            Self::ByHost { default, hosts } => (default, Some(hosts.values())),
+        };
+
+        std::iter::once(first).chain(hosts.into_iter().flatten())
+    }
+
+    /// Like [stems], but mutable.
+    ///
+    /// [stems]: Stem::stems
+    pub fn stems_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        let (first, hosts) = match self {
+            Self::Single(stem) => (stem, None),
+            Self::ByHost { default, hosts } => (default, Some(hosts.values_mut())),
+        };
+
+        std::iter::once(first).chain(hosts.into_iter().flatten())
+    }
+}
+
+/// A replacement stem used for links whose path matches `path`.
+#[derive(Deserialize)]
+pub struct StemRoute {
+    #[serde(deserialize_with = "path_regex")]
+    pub path: Regex,
+    pub stem: String,
+}
+
+/// How a pass picks the stem for a link out of the routes matching its path
+/// and its own stem for the link's host.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StemSelection {
+    /// The first matching route, or the pass's own stem if none match.
+    #[default]
+    First,
+    /// Whichever gives the shortest link, preferring the earlier one on a tie.
+    Shortest,
+}
+
+/// What to do with the query string of links from passes that don't set
+/// `keep_query`. Passes that do set it always use their own list.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryPolicy {
+    /// Keep the entire query string.
+    #[default]
+    KeepAll,
+    /// Remove the query string entirely.
+    DropAll,
+}
+
+/// How spoilered links in the source are spoilered in the reply.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SpoilerPropagation {
+    /// Each spoilered link is spoilered on its own.
+    #[default]
+    PerLink,
+    /// If any link is spoilered, the entire reply is spoilered instead.
+    WholeReply,
+}
+
+/// How spoilered links are marked in the reply.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SpoilerRender {
+    /// Hide them in Discord's spoiler tags.
+    #[default]
+    DiscordSpoiler,
+    /// Leave them visible, with a warning emoji in front.
+    EmojiPrefix,
+}
+
+/// Which of the links in a message are rewritten.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RewriteMode {
+    /// Rewrite every link.
+    #[default]
+    All,
+    /// Rewrite only the first link, and count the rest, like `+2 more links`.
+    FirstOnly,
+}
+
+/// Settings from the [Config] that apply to every pass.
+///
+/// [Config]: crate::config::Config
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ApplyOptions<'a> {
+    pub default_query: QueryPolicy,
+    pub spoilers: SpoilerPropagation,
+    pub rewrite_mode: RewriteMode,
+    pub spoiler_render: SpoilerRender,
+    /// The emoji put in front of spoilered links with [SpoilerRender::EmojiPrefix].
+    pub spoiler_emoji: &'a str,
+    /// What goes between consecutive rewritten links. Nothing follows the last.
+    pub separator: &'a str,
+    /// Whether a link more than one pass matches is only rewritten by the first
+    /// of them to run.
+    pub dedup_across_passes: bool,
+    /// Whether [Pass::apply_all] puts each pass's links under a header with
+    /// its label, if links from more than one pass were rewritten.
+    pub group_by_pass: bool,
+    /// Whether links already on a pass's own stem are skipped, instead of
+    /// being rewritten again by the pass that produced them.
+    pub skip_already_fixed: bool,
+}
+
+impl Default for ApplyOptions<'_> {
+    fn default() -> Self {
+        Self {
+            default_query: QueryPolicy::default(),
+            spoilers: SpoilerPropagation::default(),
+            rewrite_mode: RewriteMode::default(),
+            spoiler_render: SpoilerRender::default(),
+            spoiler_emoji: DEFAULT_SPOILER_EMOJI,
+            separator: " ",
+            dedup_across_passes: false,
+            group_by_pass: false,
+            skip_already_fixed: true,
+        }
+    }
+}
+
+/// The emoji put in front of spoilered links when none is configured.
+pub const DEFAULT_SPOILER_EMOJI: &str = "⚠️";
+
+impl ApplyOptions<'_> {
+    /// Writes what goes in front of something spoilered.
+    fn open_spoiler(&self, out: &mut String) {
+        match self.spoiler_render {
+            SpoilerRender::DiscordSpoiler => out.push_str("||"),
+            SpoilerRender::EmojiPrefix => {
+                out.push_str(self.spoiler_emoji);
+                out.push(' ');
+            }
+        }
+    }
+
+    /// What goes after something spoilered, given the tag that closes it as a
+    /// Discord spoiler. Emoji don't need closing.
+    fn close_spoiler(&self, tag: &'static str) -> &'static str {
+        match self.spoiler_render {
+            SpoilerRender::DiscordSpoiler => tag,
+            SpoilerRender::EmojiPrefix => "",
+        }
+    }
 }
 
 /// An enum representing the spoiler tags on a link.
@@ -17,92 +265,924 @@ pub struct Pass {
 pub enum SpoilerTags {
     /// The link is not spoilered.
     None,
-    /// The link has ||spoiler|| tags.
+    /// The link is somewhere inside a pair of ||spoiler|| tags.
     Spoiler,
-    /// The link is either started or terminated with a spoiler tag, but not
-    /// both. When resending a link with mismatched tags, the bot will just
-    /// apply full spoilers.
+    /// The link is either started or terminated with a spoiler tag, but it
+    /// isn't inside a complete spoiler. When resending a link with mismatched
+    /// tags, the bot will just apply full spoilers.
     Mismatched,
 }
 
+/// A link matched by a [Pass], split into the parts that get rewritten.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ExtractedLink<'a> {
+    /// The host the link was on, e.g. `x.com`.
+    pub host: &'a str,
+    /// The path following the matched host, starting with a `/`.
+    pub path: &'a str,
+    /// The query string without the leading `?`, or empty if there isn't one.
+    pub query: &'a str,
+    /// The fragment without the leading `#`, or empty if there isn't one.
+    pub fragment: &'a str,
+    /// The spoiler tags surrounding the link.
+    pub spoiler: SpoilerTags,
+}
+
+impl<'a> ExtractedLink<'a> {
+    /// The ID of the post the link is to, like the `123` in `/user/status/123`.
+    /// That's the last segment of the path, if it has a digit in it, which is
+    /// also what [Pass::show_id] adds to the label.
+    pub fn status_id(&self) -> Option<&'a str> {
+        link_id(self.path)
+    }
+}
+
+/// The result of running every pass over a message with [Pass::apply_all].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Rewrite {
+    /// At least one link was rewritten, giving the reply.
+    Fixed(String),
+    /// A pass matched a link, but every link was skipped, e.g. for being in
+    /// code.
+    Filtered,
+    /// No pass matched anything.
+    NoLinks,
+}
+
+impl Rewrite {
+    /// The reply, if any link was rewritten.
+    pub fn fixed(&self) -> Option<&str> {
+        match self {
+            Self::Fixed(reply) => Some(reply),
+            Self::Filtered | Self::NoLinks => None,
+        }
+    }
+}
+
+/// Messages that no pass should match, used to catch overly broad regexes.
+const NEGATIVE_SAMPLES: &[&str] = &[
+    "just some regular chat, nothing to see here",
+    "this and/or that, about 1/2 the time",
+    "see /r/rust or ~/projects/tweetboat",
+    "`let path = \"/usr/bin\";`",
+    "```\nfn main() {\n    println!(\"a/b\");\n}\n```",
+    "email me at someone@example.com/work",
+    "https://example.com/some/unrelated/path",
+];
+
 impl Pass {
-    pub fn extract<'a>(
+    /// Runs the pass against some messages that shouldn't contain links it
+    /// handles, returning the ones it matched anyway.
+    pub fn false_positives(&self) -> impl Iterator<Item = &'static str> + '_ {
+        NEGATIVE_SAMPLES
+            .iter()
+            .copied()
+            .filter(|sample| self.kind.regex().is_match(sample))
+    }
+
+    /// The full pattern the pass matches with. For link passes, that's the
+    /// configured regex padded out with named groups for spoilers, the host,
+    /// and the path. Substitute passes use theirs as-is.
+    pub fn pattern(&self) -> &str {
+        self.kind.regex().as_str()
+    }
+
+    pub fn extract<'a>(&'a self, content: &'a str) -> impl Iterator<Item = ExtractedLink<'a>> {
+        self.extract_spanned(content).map(|(_, link)| link)
+    }
+
+    /// Like [extract], along with the byte range of each link in `content`.
+    /// Substitute passes don't extract any links.
+    ///
+    /// [extract]: Pass::extract
+    fn extract_spanned<'a>(
         &'a self,
         content: &'a str,
-    ) -> impl Iterator<Item = (&'a str, &'a str, SpoilerTags)> {
-        self.regex.captures_iter(content).map(|capture| {
-            let (_, [sp_open, path, sp_close]) = capture.extract();
-            let spoiler_marker = match (!sp_open.is_empty(), !sp_close.is_empty()) {
-                (false, false) => SpoilerTags::None,
-                (true, true) => SpoilerTags::Spoiler,
-                _ => SpoilerTags::Mismatched,
+    ) -> impl Iterator<Item = (Range<usize>, ExtractedLink<'a>)> {
+        let spoilers = spoiler_spans(content);
+        let code = code_spans(content);
+        let regex = match &self.kind {
+            PassKind::Link { regex, .. } => Some(regex),
+            PassKind::Substitute { .. } => None,
+        };
+
+        regex
+            .into_iter()
+            .flat_map(move |regex| {
+                // A path can run on into the next link, as in `(a)(b)`, so
+                // each search picks up where the last link really ended
+                let mut at = 0;
+                std::iter::from_fn(move || {
+                    if at > content.len() {
+                        return None;
+                    }
+                    let capture = regex.captures_at(content, at)?;
+                    let whole = capture.get(0)?;
+                    at = match capture.name("path") {
+                        Some(path) => {
+                            let lead = capture.name("lead").map_or("", |lead| lead.as_str());
+                            path.start() + trim_path(lead, path.as_str()).len()
+                        }
+                        // Nothing was matched, so step past the next character
+                        None if whole.is_empty() => {
+                            let next = content[whole.end()..].chars().next();
+                            whole.end() + next.map_or(1, char::len_utf8)
+                        }
+                        None => whole.end(),
+                    };
+                    Some(capture)
+                })
+            })
+            .filter_map(move |capture| {
+                // Groups are named, so ones in the configured regex don't
+                // shift them
+                let group = |name| capture.name(name).map_or("", |group| group.as_str());
+                let (Some(core), Some(path)) = (capture.name("core"), capture.name("path")) else {
+                    // The configured regex closed its group early, so part of
+                    // the pattern can match without the rest
+                    tracing::warn!(
+                        "Pass {:?} matched {:?} without a link, check its regex",
+                        self.label,
+                        &capture[0]
+                    );
+                    return None;
+                };
+                let start = path.start();
+                let [lead, sp_open, matched, path, sp_close] =
+                    ["lead", "sp_open", "core", "path", "sp_close"].map(group);
+
+                let path = trim_path(lead, path);
+                let span = core.start()..start + path.len();
+
+                // Links in code are examples, not something to fix. They're
+                // still matched, even right after a backtick, so a message of
+                // only those is Filtered rather than NoLinks
+                if code.iter().any(|span| span.contains(&start)) {
+                    return None;
+                }
+
+                let spoiler = if spoilers.iter().any(|span| span.contains(&start)) {
+                    SpoilerTags::Spoiler
+                } else if !sp_open.is_empty() || !sp_close.is_empty() {
+                    SpoilerTags::Mismatched
+                } else {
+                    SpoilerTags::None
+                };
+
+                let (path, fragment) = path.split_once('#').unwrap_or((path, ""));
+                let (path, query) = path.split_once('?').unwrap_or((path, ""));
+                if self.skip_media && is_media_path(path) {
+                    return None;
+                }
+                let segments = path
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .count();
+                if self.min_path_segments.is_some_and(|min| segments < min) {
+                    return None;
+                }
+                let host = matched.split_once("://").map_or(matched, |(_, host)| host);
+
+                let link = ExtractedLink {
+                    host,
+                    path,
+                    query,
+                    fragment,
+                    spoiler,
+                };
+                Some((span, link))
+            })
+    }
+
+    /// Picks the stem for a link: the first route whose pattern matches the
+    /// path, or the pass's own stem for the link's host if none do. Substitute
+    /// passes have no stem of their own, so that's empty.
+    ///
+    /// With [StemSelection::Shortest], the pass's own stem and every matching
+    /// route are candidates instead, and the one giving the shortest link wins.
+    pub fn stem_for(&self, host: &str, path: &str) -> &str {
+        let mut routes = self
+            .routes
+            .iter()
+            .filter(|route| route.path.is_match(path))
+            .map(|route| route.stem.as_str());
+        let own = || self.kind.stem().map_or("", |stem| stem.for_host(host));
+
+        match self.stem_selection {
+            StemSelection::First => routes.next().unwrap_or_else(own),
+            // Every candidate is followed by the same path, so the shortest
+            // stem gives the shortest link
+            StemSelection::Shortest => routes
+                .chain(self.kind.stem().map(|_| own()))
+                .min_by_key(|stem| stem.len())
+                .unwrap_or(""),
+        }
+    }
+
+    /// The message with everything this pass matched replaced, if it's a
+    /// substitute pass that matched anything.
+    pub fn substitute<'a>(&self, content: &'a str) -> Option<Cow<'a, str>> {
+        let PassKind::Substitute { regex, replacement } = &self.kind else {
+            return None;
+        };
+
+        match regex.replace_all(content, replacement.as_str()) {
+            Cow::Borrowed(_) => None,
+            replaced => Some(replaced),
+        }
+    }
+
+    /// Whether `host` is the host of one of this pass's stems, or one of its
+    /// routes' stems, so links on it have already been fixed.
+    pub fn is_stem_host(&self, host: &str) -> bool {
+        let stems = self.kind.stem().into_iter().flat_map(Stem::stems);
+        let routes = self.routes.iter().map(|route| &route.stem);
+        stems
+            .chain(routes)
+            .any(|stem| stem_host(stem).eq_ignore_ascii_case(host))
+    }
+
+    /// The URL a link is rewritten to, without its query, fragment, or a
+    /// trailing `/`. Every way of linking to the same post gives the same URL.
+    pub fn normalized_url(&self, link: &ExtractedLink) -> String {
+        let path = clean_path(link.path);
+        let stem = self.stem_for(link.host, &path);
+        [stem, path.trim_end_matches('/')].concat()
+    }
+
+    pub fn apply(&self, content: &str, options: ApplyOptions) -> Option<String> {
+        // Most messages have no links, so skip finding code and spoilers
+        if !self.kind.regex().is_match(content) {
+            return None;
+        }
+
+        let mut out = String::new();
+        self.apply_into(content, options, &mut out).then_some(out)
+    }
+
+    /// Like [apply], but appends the rewritten links to `out` instead of
+    /// allocating a new string. Returns whether anything was appended. The
+    /// separator only goes between the links this appends, not before them.
+    ///
+    /// [apply]: Pass::apply
+    pub fn apply_into(&self, content: &str, options: ApplyOptions, out: &mut String) -> bool {
+        let start = out.len();
+        if let Some(replaced) = self.substitute(content) {
+            out.push_str(&replaced);
+        }
+
+        let links = self
+            .extract(content)
+            .filter(|link| !(options.skip_already_fixed && self.is_stem_host(link.host)));
+        for link in links {
+            if out.len() > start {
+                out.push_str(options.separator);
+            } else {
+                self.write_type_label(out);
+            }
+            self.write_link(link, options, out);
+        }
+
+        out.len() > start
+    }
+
+    /// Appends what goes in front of the pass's links with `link_type_label`.
+    fn write_type_label(&self, out: &mut String) {
+        if self.link_type_label {
+            let _ = write!(out, "{} fixed: ", self.label);
+        }
+    }
+
+    /// Appends the rewritten form of one link to `out`.
+    fn write_link(&self, link: ExtractedLink, options: ApplyOptions, out: &mut String) {
+        let label = &self.label;
+        let ExtractedLink {
+            host,
+            path,
+            query,
+            fragment,
+            spoiler,
+        } = link;
+        let spoil = spoiler != SpoilerTags::None && options.spoilers == SpoilerPropagation::PerLink;
+        let path = clean_path(path);
+        let stem = self.stem_for(host, &path);
+
+        if spoil {
+            options.open_spoiler(out);
+        }
+        let _ = match link_id(&path).filter(|_| self.show_id) {
+            Some(id) => write!(out, "[`{label} {id}`]({stem}{path}"),
+            None => write!(out, "[`{label}`]({stem}{path}"),
+        };
+        match (&self.keep_query, options.default_query) {
+            (None, QueryPolicy::KeepAll) if !query.is_empty() => {
+                out.push('?');
+                out.push_str(query);
+            }
+            (Some(keep), _) if !keep.is_empty() => out.push_str(&filter_query(query, keep)),
+            _ => {}
+        }
+        if !fragment.is_empty() {
+            out.push('#');
+            out.push_str(fragment);
+        }
+        out.push(')');
+        if spoil {
+            out.push_str(options.close_spoiler(" ||"));
+        }
+    }
+
+    /// The number of replies this pass went into since it was loaded. Only
+    /// replies that were [recorded] count, so previews and checks don't.
+    ///
+    /// [recorded]: Pass::record_fires
+    pub fn fire_count(&self) -> u64 {
+        self.fires.load(Ordering::Relaxed)
+    }
+
+    /// Runs just this pass over `content`, like [apply], but telling apart
+    /// links it skipped from no links at all like [apply_all] does.
+    ///
+    /// [apply]: Pass::apply
+    /// [apply_all]: Pass::apply_all
+    pub fn rewrite(&self, content: &str, options: ApplyOptions) -> Rewrite {
+        match self.apply(content, options) {
+            Some(fixed) => Rewrite::Fixed(fixed),
+            None if self.kind.regex().is_match(content) => Rewrite::Filtered,
+            None => Rewrite::NoLinks,
+        }
+    }
+
+    /// Runs every pass over `content` and joins their output, in order of
+    /// [priority]. The passes can be owned or borrowed, so a subset of them
+    /// can be picked out without cloning. Substitute passes' messages come
+    /// after every link.
+    ///
+    /// [priority]: Pass::priority
+    pub fn apply_all<P: Borrow<Self>>(
+        passes: &[P],
+        content: &str,
+        options: ApplyOptions,
+    ) -> Rewrite {
+        let passes = Self::by_priority(passes);
+        if options.rewrite_mode == RewriteMode::FirstOnly {
+            return Self::apply_first(&passes, content, options);
+        }
+
+        let spoil_all = Self::spoils_all(&passes, content, options);
+
+        // The reply is usually about as long as the links it was made from
+        let mut out = String::with_capacity(content.len());
+        if spoil_all {
+            options.open_spoiler(&mut out);
+        }
+
+        let links: Vec<_> = Self::links(&passes, content, options).collect();
+        let substitutions: Vec<_> = Self::substitutions(&passes, content).collect();
+        // Links come pass by pass, so each change of pass is another pass
+        let link_passes = links
+            .windows(2)
+            .filter(|pair| !std::ptr::eq(pair[0].0, pair[1].0))
+            .count()
+            + usize::from(!links.is_empty());
+        let grouped = options.group_by_pass && link_passes + substitutions.len() > 1;
+
+        let mut last: Option<&Self> = None;
+        for (pass, link) in links {
+            let first = !last.is_some_and(|last| std::ptr::eq(last, pass));
+            Self::join(pass, last, grouped, options, &mut out);
+            last = Some(pass);
+            if first {
+                pass.write_type_label(&mut out);
+            }
+            pass.write_link(link, options, &mut out);
+        }
+
+        for (pass, replaced) in substitutions {
+            Self::join(pass, last, grouped, options, &mut out);
+            last = Some(pass);
+            out.push_str(&replaced);
+        }
+
+        if last.is_none() {
+            return Self::unfixed(&passes, content);
+        }
+
+        if spoil_all {
+            out.push_str(options.close_spoiler("||"));
+        }
+
+        Rewrite::Fixed(out)
+    }
+
+    /// Writes what goes before the next part of [apply_all]'s reply, which is
+    /// from `pass`, given the pass the `last` part was from. The first part
+    /// from each pass starts its group if the reply is `grouped`.
+    ///
+    /// [apply_all]: Pass::apply_all
+    fn join(
+        pass: &Self,
+        last: Option<&Self>,
+        grouped: bool,
+        options: ApplyOptions,
+        out: &mut String,
+    ) {
+        if last.is_some_and(|last| std::ptr::eq(last, pass)) {
+            out.push_str(options.separator);
+            return;
+        }
+
+        if grouped {
+            if last.is_some() {
+                out.push('\n');
+            }
+            let _ = writeln!(out, "**{}**", pass.label);
+        } else if last.is_some() {
+            out.push_str(options.separator);
+        }
+    }
+
+    /// [apply_all] with [RewriteMode::FirstOnly]. The passes are in order of
+    /// [priority], and only the first link is rewritten. Substitute passes
+    /// only count if there are no links, in which case the first of them that
+    /// matched is used.
+    ///
+    /// [apply_all]: Pass::apply_all
+    /// [priority]: Pass::priority
+    fn apply_first(passes: &[&Self], content: &str, options: ApplyOptions) -> Rewrite {
+        let mut links = Self::links(passes, content, options);
+        let Some((pass, first)) = links.next() else {
+            return match Self::substitutions(passes, content).next() {
+                Some((_, replaced)) => Rewrite::Fixed(replaced.into_owned()),
+                None => Self::unfixed(passes, content),
             };
+        };
+        let rest = links.count();
 
-            let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let spoil_all = Self::spoils_all(passes, content, options);
+        let mut out = String::new();
+        if spoil_all {
+            options.open_spoiler(&mut out);
+        }
 
-            (path, query, spoiler_marker)
-        })
+        pass.write_type_label(&mut out);
+        pass.write_link(first, options, &mut out);
+        if rest > 0 {
+            let plural = if rest == 1 { "" } else { "s" };
+            let _ = write!(out, "{}+{rest} more link{plural}", options.separator);
+        }
+
+        if spoil_all {
+            out.push_str(options.close_spoiler("||"));
+        }
+
+        Rewrite::Fixed(out)
     }
 
-    pub fn apply<'a>(&'a self, content: &'a str) -> Option<String> {
-        let Self { label, stem, .. } = self;
+    /// What [apply_all] gives when no pass fired, which depends on whether
+    /// any of them matched a link only to skip it.
+    ///
+    /// [apply_all]: Pass::apply_all
+    fn unfixed(passes: &[&Self], content: &str) -> Rewrite {
+        if passes
+            .iter()
+            .any(|pass| pass.kind.regex().is_match(content))
+        {
+            Rewrite::Filtered
+        } else {
+            Rewrite::NoLinks
+        }
+    }
 
-        let out =
-            self.extract(content)
-                .fold(String::new(), |mut out, (path, query, spoiler_tags)| {
-                    let spoil = spoiler_tags != SpoilerTags::None;
+    /// Like [apply_all], but breaks the reply into chunks of at most `limit`
+    /// bytes, only ever between links. A link too long for a chunk of its
+    /// own still gets one. Links aren't grouped by pass.
+    ///
+    /// [apply_all]: Pass::apply_all
+    pub fn apply_all_split<P: Borrow<Self>>(
+        passes: &[P],
+        content: &str,
+        options: ApplyOptions,
+        limit: usize,
+    ) -> Vec<String> {
+        let passes = Self::by_priority(passes);
+        let mut open = String::new();
+        let mut close = "";
+        if Self::spoils_all(&passes, content, options) {
+            options.open_spoiler(&mut open);
+            close = options.close_spoiler("||");
+        }
 
-                    let query_string = match &self.keep_query {
-                        None => format!("?{query}"),
-                        Some(keep) if !keep.is_empty() => filter_query(query, keep),
-                        _ => String::new(),
-                    };
+        let mut last: Option<&Self> = None;
+        let links = Self::links(&passes, content, options).map(move |(pass, extracted)| {
+            let mut link = String::new();
+            if !last.is_some_and(|last| std::ptr::eq(last, pass)) {
+                pass.write_type_label(&mut link);
+                last = Some(pass);
+            }
+            pass.write_link(extracted, options, &mut link);
+            link
+        });
+        let substituted =
+            Self::substitutions(&passes, content).map(|(_, replaced)| replaced.into_owned());
 
-                    if spoil {
-                        let _ = write!(&mut out, "||");
-                    }
-                    let _ = write!(&mut out, "[`{label}`]({stem}{path}{query_string}) ");
-                    if spoil {
-                        let _ = write!(&mut out, "|| ");
+        let mut chunks = Vec::new();
+        let mut chunk = String::new();
+        for link in links.chain(substituted) {
+            let grown = chunk.len() + options.separator.len() + link.len() + close.len();
+            if !chunk.is_empty() && grown > limit {
+                chunk.push_str(close);
+                chunks.push(std::mem::take(&mut chunk));
+            }
+
+            if chunk.is_empty() {
+                chunk.push_str(&open);
+            } else {
+                chunk.push_str(options.separator);
+            }
+            chunk.push_str(&link);
+        }
+
+        if !chunk.is_empty() {
+            chunk.push_str(close);
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    /// Whether the whole reply should be spoilered. Links weren't spoilered
+    /// individually, so any spoiler covers all of them at once.
+    fn spoils_all(passes: &[&Self], content: &str, options: ApplyOptions) -> bool {
+        options.spoilers == SpoilerPropagation::WholeReply
+            && passes.iter().any(|pass| {
+                pass.extract(content)
+                    .any(|link| link.spoiler != SpoilerTags::None)
+            })
+    }
+
+    /// Every link the passes match, pass by pass in the order given. With
+    /// `skip_already_fixed`, links on the stems of the pass matching them are
+    /// left out like in [apply_into], and with `dedup_across_passes`, so are
+    /// links overlapping one an earlier pass matched.
+    ///
+    /// [apply_into]: Pass::apply_into
+    pub fn links<'a>(
+        passes: &'a [&'a Self],
+        content: &'a str,
+        options: ApplyOptions,
+    ) -> impl Iterator<Item = (&'a Self, ExtractedLink<'a>)> {
+        let dedup = options.dedup_across_passes;
+        let mut claimed: Vec<Range<usize>> = Vec::new();
+        passes
+            .iter()
+            .flat_map(move |&pass| {
+                pass.extract_spanned(content)
+                    .map(move |(span, link)| (pass, span, link))
+            })
+            .filter_map(move |(pass, span, link)| {
+                // Only its own stems, so a pass can still take another's output
+                if options.skip_already_fixed && pass.is_stem_host(link.host) {
+                    return None;
+                }
+
+                if dedup {
+                    let overlaps =
+                        |other: &Range<usize>| other.start < span.end && span.start < other.end;
+                    if claimed.iter().any(overlaps) {
+                        return None;
                     }
+                    claimed.push(span);
+                }
 
-                    out
-                });
+                Some((pass, link))
+            })
+    }
+
+    /// Counts a reply [apply_all] made from `content` towards the [fire_count]
+    /// of every pass that went into it. Meant for replies that were actually
+    /// sent, so nothing else is counted.
+    ///
+    /// [apply_all]: Pass::apply_all
+    /// [fire_count]: Pass::fire_count
+    pub fn record_fires<P: Borrow<Self>>(passes: &[P], content: &str, options: ApplyOptions) {
+        let passes = Self::by_priority(passes);
+        let linked = Self::links(&passes, content, options).map(|(pass, _)| pass);
+        let substituted = Self::substitutions(&passes, content).map(|(pass, _)| pass);
+        let mut fired: Vec<_> = linked.chain(substituted).collect();
+        if options.rewrite_mode == RewriteMode::FirstOnly {
+            fired.truncate(1);
+        }
 
-        (!out.is_empty()).then_some(out)
+        // Links come pass by pass, so repeats are next to each other
+        fired.dedup_by(|a, b| std::ptr::eq(*a, *b));
+        for pass in fired {
+            pass.fires.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
-    pub fn apply_all(passes: &[Self], content: &str) -> Option<String> {
-        let mut transformed = None;
-        for pass in passes {
-            if let Some(patched) = pass.apply(content) {
-                transformed.get_or_insert(String::new()).push_str(&patched);
-            }
+    /// The message as each substitute pass that matched anything rewrote it,
+    /// in the order given.
+    fn substitutions<'a>(
+        passes: &'a [&'a Self],
+        content: &'a str,
+    ) -> impl Iterator<Item = (&'a Self, Cow<'a, str>)> {
+        passes
+            .iter()
+            .filter_map(move |&pass| Some((pass, pass.substitute(content)?)))
+    }
+
+    /// The passes in the order they run, highest priority first.
+    fn by_priority<P: Borrow<Self>>(passes: &[P]) -> Vec<&Self> {
+        // Sorting is stable, so config order breaks ties
+        let mut ordered: Vec<_> = passes.iter().map(<P as Borrow<Self>>::borrow).collect();
+        ordered.sort_by_key(|pass| Reverse(pass.priority));
+        ordered
+    }
+}
+
+/// The flags a pass can set on its regex, as in `(?flags)`.
+const REGEX_FLAGS: &str = "imsuxU";
+
+/// The schemes `any_scheme` replaces at the start of a pass's regex.
+const REGEX_SCHEMES: [&str; 3] = ["https?://", "https://", "http://"];
+
+/// The `kind` key of a pass entry.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum KindName {
+    #[default]
+    Link,
+    Substitute,
+}
+
+/// Deserializes what a pass does from a pass entry. Link passes need a `stem`,
+/// and substitute passes a `replacement`, which is expanded as in
+/// [Regex::replace_all].
+///
+/// A link pass's regex is padded out with spoiler tags and spacing, and
+/// captured to find the link's host. The pass's `flags` only apply inside that
+/// capture, not to the padding. The captures are named, so the decoded string
+/// can have groups of its own. A substitute pass's regex is used as-is, apart
+/// from its `flags`.
+///
+/// With `any_scheme`, the scheme the decoded string starts with is made
+/// optional and also matches `http://`. The host capture then covers whatever
+/// scheme the link had, so the stem's own scheme always replaces it.
+fn pass_kind<'de, D: Deserializer<'de>>(de: D) -> Result<PassKind, D::Error> {
+    use serde::de::Error as _;
+
+    #[derive(Deserialize)]
+    struct Entry {
+        #[serde(default)]
+        kind: KindName,
+        regex: String,
+        #[serde(default)]
+        flags: String,
+        #[serde(default)]
+        any_scheme: bool,
+        stem: Option<Stem>,
+        replacement: Option<String>,
+    }
+
+    let Entry {
+        kind,
+        regex: core,
+        flags,
+        any_scheme,
+        stem,
+        replacement,
+    } = Entry::deserialize(de)?;
+    let mut trimmed = Cow::Borrowed(core.trim());
+    if trimmed.len() != core.len() {
+        tracing::warn!("Trimmed whitespace around regex {core:?}");
+    }
+
+    if any_scheme {
+        let rest = REGEX_SCHEMES
+            .iter()
+            .find_map(|scheme| trimmed.strip_prefix(scheme))
+            .ok_or_else(|| {
+                D::Error::custom(format!(
+                    "any_scheme needs regex {core:?} to start with \"https://\""
+                ))
+            })?;
+        trimmed = Cow::Owned(format!("(?:https?://)?{rest}"));
+    }
+
+    if let Some(flag) = flags.chars().find(|&flag| !REGEX_FLAGS.contains(flag)) {
+        return Err(D::Error::custom(format!("unknown regex flag {flag:?}")));
+    }
+
+    let flags = if flags.is_empty() {
+        String::new()
+    } else {
+        format!("(?{flags})")
+    };
+
+    match kind {
+        KindName::Link => {
+            let stem = stem.ok_or_else(|| D::Error::missing_field("stem"))?;
+            let pattern = format!(
+                "(?:^|\\s|(?P<lead>[:(\\[<\"`]))(?P<sp_open>\\|\\||)(?P<core>{flags}{trimmed})(?P<path>/(?:[^\\s|]|\\|[^\\s|])+)(?P<sp_close>\\|\\||)"
+            );
+            let regex = Regex::new(&pattern).map_err(D::Error::custom)?;
+            Ok(PassKind::Link { regex, stem })
         }
+        KindName::Substitute => {
+            let replacement = replacement.ok_or_else(|| D::Error::missing_field("replacement"))?;
+            let regex = Regex::new(&format!("{flags}{trimmed}")).map_err(D::Error::custom)?;
+            Ok(PassKind::Substitute { regex, replacement })
+        }
+    }
+}
 
-        transformed
+/// The character that closes a link opened with `lead`, the punctuation just
+/// before it, if it's a bracket, quote or backtick.
+fn closing(lead: &str) -> Option<char> {
+    match lead {
+        "(" => Some(')'),
+        "[" => Some(']'),
+        "<" => Some('>'),
+        "\"" => Some('"'),
+        "`" => Some('`'),
+        _ => None,
     }
 }
 
-/// Deserializes the regex from a pass entry. This pads out the decoded string
-/// with spoiler tags and spacing.
-fn pass_regex<'de, D: Deserializer<'de>>(de: D) -> Result<Regex, D::Error> {
+/// The part of a matched path that's really part of the link. A link opened
+/// with a bracket or quote ends at its pair, which isn't part of it, and
+/// punctuation ending a sentence is left off, along with a closing bracket
+/// that nothing in the path opened.
+fn trim_path<'a>(lead: &str, path: &'a str) -> &'a str {
+    let mut path = match (lead.chars().next(), closing(lead)) {
+        (Some(open), Some(close)) => {
+            let mut depth = 0;
+            let end = path.char_indices().find(|&(_, c)| {
+                if c == close && depth == 0 {
+                    return true;
+                }
+                if open != close && c == open {
+                    depth += 1;
+                } else if c == close {
+                    depth -= 1;
+                }
+                false
+            });
+            end.map_or(path, |(idx, _)| &path[..idx])
+        }
+        _ => path,
+    };
+
+    loop {
+        let trimmed = path.trim_end_matches(['.', ',', '!', '?', ';', ':', '\'', '"', '`']);
+        let unopened = |open, close| {
+            trimmed.ends_with(close)
+                && trimmed.matches(close).count() > trimmed.matches(open).count()
+        };
+        let trimmed = if unopened('(', ')') || unopened('[', ']') {
+            &trimmed[..trimmed.len() - 1]
+        } else {
+            trimmed
+        };
+        if trimmed.len() == path.len() {
+            return path;
+        }
+        path = trimmed;
+    }
+}
+
+/// The host a stem links to, e.g. `vxtwitter.com` for `https://vxtwitter.com`.
+fn stem_host(stem: &str) -> &str {
+    let host = stem.split_once("://").map_or(stem, |(_, host)| host);
+    host.split('/').next().unwrap_or(host)
+}
+
+/// Finds the byte ranges of the message covered by spoilers, between each pair
+/// of `||` tags.
+fn spoiler_spans(content: &str) -> Vec<Range<usize>> {
+    let tags: Vec<_> = content.match_indices("||").map(|(idx, _)| idx).collect();
+    tags.chunks_exact(2)
+        .map(|pair| pair[0] + 2..pair[1])
+        .collect()
+}
+
+/// Finds the byte ranges of the message in inline code or code blocks. A run of
+/// backticks opens a span that's closed by the next run of the same length, so
+/// this covers both `code` and ```fenced``` blocks.
+fn code_spans(content: &str) -> Vec<Range<usize>> {
+    // Backticks are ASCII, so these indices are always on char boundaries
+    let bytes = content.as_bytes();
+    let run_at = |idx: usize| bytes[idx..].iter().take_while(|&&b| b == b'`').count();
+
+    let mut spans = Vec::new();
+    let mut idx = 0;
+    while let Some(offset) = content[idx..].find('`') {
+        let open = idx + offset;
+        let len = run_at(open);
+
+        let mut close = open + len;
+        let end = loop {
+            match content[close..].find('`') {
+                Some(offset) if run_at(close + offset) == len => break Some(close + offset),
+                Some(offset) => close += offset + run_at(close + offset),
+                None => break None,
+            }
+        };
+
+        match end {
+            Some(end) => {
+                spans.push(open..end + len);
+                idx = end + len;
+            }
+            // Unclosed, so it's just a backtick
+            None => idx = open + len,
+        }
+    }
+
+    spans
+}
+
+/// Deserializes a route's path pattern, which is used as-is.
+fn path_regex<'de, D: Deserializer<'de>>(de: D) -> Result<Regex, D::Error> {
     use serde::de::Error as _;
 
-    let core = String::deserialize(de)?;
-    Regex::new(&["(?:^|\\s)", "(\\|\\||)", &core, "(/\\S+)", "(\\s?\\|\\||)"].concat())
-        .map_err(D::Error::custom)
+    Regex::new(&String::deserialize(de)?).map_err(D::Error::custom)
 }
 
-/// Removes all query parameters from a query string except those in the provided list
-fn filter_query(qs: &str, keep: &Vec<String>) -> String {
+/// Inline markdown that ends up inside a link's path, like bold around the
+/// username in `https://x.com/**user**/status/1`. Single `*` and `_` and `__`
+/// are left alone, since real paths have them.
+const PATH_MARKDOWN: [&str; 2] = ["**", "~~"];
+
+/// Invisible characters that break a link, usually pasted along with it.
+const ZERO_WIDTH: [char; 5] = ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}'];
+
+/// Removes [PATH_MARKDOWN] and [ZERO_WIDTH] characters from a path, which the
+/// link's site wouldn't recognize. Only allocates if there are any.
+fn clean_path(path: &str) -> Cow<'_, str> {
+    if !path.contains(ZERO_WIDTH) && !PATH_MARKDOWN.iter().any(|token| path.contains(token)) {
+        return Cow::Borrowed(path);
+    }
+
+    // Zero-width characters go first, since they can split up markdown
+    let mut cleaned = path.replace(ZERO_WIDTH, "");
+    for token in PATH_MARKDOWN {
+        cleaned = cleaned.replace(token, "");
+    }
+
+    Cow::Owned(cleaned)
+}
+
+/// File extensions of direct links to media.
+const MEDIA_EXTENSIONS: [&str; 8] = ["jpg", "jpeg", "png", "gif", "webp", "mp4", "mov", "webm"];
+
+/// Path segments that lead to one of a post's attachments instead of the post.
+const MEDIA_SEGMENTS: [&str; 2] = ["photo", "video"];
+
+/// Whether a path is to media rather than a post: it ends in one of the
+/// [MEDIA_EXTENSIONS], or has one of the [MEDIA_SEGMENTS] in it.
+fn is_media_path(path: &str) -> bool {
+    let file = path.rsplit('/').find(|segment| !segment.is_empty());
+    let extension = file
+        .and_then(|file| file.rsplit_once('.'))
+        .map(|(_, ext)| ext);
+    let has_extension = extension.is_some_and(|extension| {
+        MEDIA_EXTENSIONS
+            .iter()
+            .any(|media| extension.eq_ignore_ascii_case(media))
+    });
+
+    has_extension
+        || path
+            .split('/')
+            .any(|segment| MEDIA_SEGMENTS.contains(&segment))
+}
+
+/// The last segment of a path, if it looks like an ID, which is when it has a
+/// digit in it.
+fn link_id(path: &str) -> Option<&str> {
+    let segment = path.split('/').rfind(|segment| !segment.is_empty())?;
+    segment
+        .bytes()
+        .any(|b| b.is_ascii_digit())
+        .then_some(segment)
+}
+
+/// Removes all query parameters from a query string except those in the provided list,
+/// giving an empty string rather than a lone `?` if none are left
+fn filter_query(qs: &str, keep: &[String]) -> String {
     let query_map: HashMap<_, _> = qs.split('&').filter_map(|p| p.split_once('=')).collect();
 
     let params = query_map
         .iter()
-        .filter_map(|(k, v)| keep.contains(&k.to_string()).then(|| format!("{k}={v}")))
+        .filter(|(k, _)| keep.contains(&k.to_string()))
+        .map(|(k, v)| format!("{k}={v}"))
         .collect::<Vec<_>>()
         .join("&");
 
+    if params.is_empty() {
+        return String::new();
+    }
+
     format!("?{params}")
 }
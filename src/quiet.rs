@@ -0,0 +1,101 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer};
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// A daily window during which the bot doesn't post new replies, like
+/// `22:00` to `06:00`. The times are in UTC, shifted by `utc_offset_minutes`
+/// for communities in other timezones.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct QuietHours {
+    #[serde(deserialize_with = "time_of_day")]
+    pub start: u16,
+    #[serde(deserialize_with = "time_of_day")]
+    pub end: u16,
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+impl QuietHours {
+    /// Whether `time` falls in the window. The start is included and the end
+    /// isn't, and a window that ends before it starts wraps around midnight.
+    /// A window that ends when it starts is empty.
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let minutes = i64::try_from(secs / 60).unwrap_or(i64::MAX);
+        let minute = (minutes + i64::from(self.utc_offset_minutes)).rem_euclid(MINUTES_PER_DAY);
+        let (start, end) = (i64::from(self.start), i64::from(self.end));
+
+        if start <= end {
+            (start..end).contains(&minute)
+        } else {
+            minute >= start || minute < end
+        }
+    }
+}
+
+/// Deserializes an `HH:MM` time of day as the minutes since midnight.
+fn time_of_day<'de, D: Deserializer<'de>>(de: D) -> Result<u16, D::Error> {
+    let time = String::deserialize(de)?;
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+        let hours: u16 = hours.parse().ok()?;
+        let minutes: u16 = minutes.parse().ok()?;
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    });
+
+    parsed.ok_or_else(|| serde::de::Error::custom(format!("invalid time of day {time:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::QuietHours;
+
+    fn at(hours: u64, minutes: u64) -> SystemTime {
+        // Some day well after the epoch
+        UNIX_EPOCH + Duration::from_secs(((20_000 * 24 + hours) * 60 + minutes) * 60)
+    }
+
+    #[test]
+    fn same_day() {
+        let quiet: QuietHours = toml::from_str("start = \"09:30\"\nend = \"17:00\"").unwrap();
+        assert!(!quiet.contains(at(9, 29)));
+        assert!(quiet.contains(at(9, 30)));
+        assert!(quiet.contains(at(12, 0)));
+        assert!(!quiet.contains(at(17, 0)));
+        assert!(!quiet.contains(at(23, 0)));
+    }
+
+    #[test]
+    fn midnight_wrap() {
+        let quiet: QuietHours = toml::from_str("start = \"22:00\"\nend = \"06:00\"").unwrap();
+        assert!(quiet.contains(at(22, 0)));
+        assert!(quiet.contains(at(23, 59)));
+        assert!(quiet.contains(at(0, 0)));
+        assert!(quiet.contains(at(5, 59)));
+        assert!(!quiet.contains(at(6, 0)));
+        assert!(!quiet.contains(at(12, 0)));
+        assert!(!quiet.contains(at(21, 59)));
+    }
+
+    #[test]
+    fn utc_offset() {
+        // 22:00 to 06:00 at UTC+2 is 20:00 to 04:00 in UTC
+        let quiet: QuietHours =
+            toml::from_str("start = \"22:00\"\nend = \"06:00\"\nutc_offset_minutes = 120").unwrap();
+        assert!(quiet.contains(at(20, 0)));
+        assert!(quiet.contains(at(3, 59)));
+        assert!(!quiet.contains(at(4, 0)));
+        assert!(!quiet.contains(at(19, 59)));
+    }
+
+    #[test]
+    fn invalid_times() {
+        assert!(toml::from_str::<QuietHours>("start = \"24:00\"\nend = \"06:00\"").is_err());
+        assert!(toml::from_str::<QuietHours>("start = \"10\"\nend = \"06:00\"").is_err());
+    }
+}
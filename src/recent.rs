@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use twilight_model::id::{marker::ChannelMarker, Id};
+
+/// The links the bot fixed recently in each channel, used to skip messages
+/// that only repeat links fixed moments ago.
+///
+/// Links are keyed by their [normalized URL], so different ways of linking to
+/// the same post count as the same link. Fixes older than the window are
+/// dropped as the channel is checked.
+///
+/// [normalized URL]: crate::pass::Pass::normalized_url
+pub struct RecentFixes {
+    window: Duration,
+    channels: HashMap<Id<ChannelMarker>, Vec<(String, Instant)>>,
+}
+
+impl RecentFixes {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Whether every one of `links` was fixed in the channel within the window
+    /// before `now`. Always false for an empty list.
+    pub fn all_recent(
+        &mut self,
+        channel_id: Id<ChannelMarker>,
+        links: &[String],
+        now: Instant,
+    ) -> bool {
+        let Some(fixes) = self.channels.get_mut(&channel_id) else {
+            return false;
+        };
+
+        fixes.retain(|(_, at)| now.saturating_duration_since(*at) <= self.window);
+        if fixes.is_empty() {
+            self.channels.remove(&channel_id);
+            return false;
+        }
+
+        !links.is_empty()
+            && links
+                .iter()
+                .all(|link| fixes.iter().any(|(fixed, _)| fixed == link))
+    }
+
+    /// Remembers that `links` were fixed in the channel at `now`.
+    pub fn record(&mut self, channel_id: Id<ChannelMarker>, links: Vec<String>, now: Instant) {
+        let fixes = self.channels.entry(channel_id).or_default();
+        fixes.retain(|(fixed, _)| !links.contains(fixed));
+        fixes.extend(links.into_iter().map(|link| (link, now)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use twilight_model::id::Id;
+
+    use super::RecentFixes;
+
+    #[test]
+    fn window() {
+        let mut recent = RecentFixes::new(Duration::from_secs(30));
+        let start = Instant::now();
+        let links = |links: &[&str]| {
+            links
+                .iter()
+                .map(|&link| link.to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        recent.record(Id::new(1), links(&["a", "b"]), start);
+
+        let soon = start + Duration::from_secs(10);
+        assert!(recent.all_recent(Id::new(1), &links(&["a"]), soon));
+        assert!(recent.all_recent(Id::new(1), &links(&["b", "a"]), soon));
+        assert!(!recent.all_recent(Id::new(1), &links(&["a", "c"]), soon));
+        assert!(!recent.all_recent(Id::new(1), &links(&[]), soon));
+
+        // Other channels don't count
+        assert!(!recent.all_recent(Id::new(2), &links(&["a"]), soon));
+
+        // Fixing a link again restarts its window
+        recent.record(Id::new(1), links(&["a"]), soon);
+        let later = start + Duration::from_secs(35);
+        assert!(recent.all_recent(Id::new(1), &links(&["a"]), later));
+        assert!(!recent.all_recent(Id::new(1), &links(&["b"]), later));
+
+        let much_later = start + Duration::from_secs(60);
+        assert!(!recent.all_recent(Id::new(1), &links(&["a"]), much_later));
+        assert!(recent.channels.is_empty());
+    }
+}
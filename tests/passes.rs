@@ -1,5 +1,8 @@
 use tweetboat::config::Config;
-use tweetboat::pass::SpoilerTags;
+use tweetboat::pass::{
+    ApplyOptions, ExtractedLink, Pass, QueryPolicy, Rewrite, RewriteMode, SpoilerPropagation,
+    SpoilerRender, SpoilerTags,
+};
 
 #[test]
 fn standard_passes() {
@@ -10,7 +13,7 @@ fn standard_passes() {
                 These are just some random test urls.
                 - https://x.com/rustbeltenjoyer/status/1776056709737320578?s=46&t=owouwu
                 - ||https://www.instagram.com/p/C5W2QwZrt-Z/ ||
-                - https://www.tiktok.com/t/ZPRTX3AwH/
+                - https://www.tiktok.com/t/ZPRTX3AwH/#top
             ",
         )
     });
@@ -19,20 +22,1223 @@ fn standard_passes() {
 
     assert_eq!(
         extracted.next(),
-        Some((
-            "/rustbeltenjoyer/status/1776056709737320578",
-            "s=46&t=owouwu",
-            SpoilerTags::None
-        ))
+        Some(ExtractedLink {
+            host: "x.com",
+            path: "/rustbeltenjoyer/status/1776056709737320578",
+            query: "s=46&t=owouwu",
+            fragment: "",
+            spoiler: SpoilerTags::None
+        })
     );
     assert_eq!(
         extracted.next(),
-        Some(("/p/C5W2QwZrt-Z/", "", SpoilerTags::Spoiler))
+        Some(ExtractedLink {
+            host: "www.instagram.com",
+            path: "/p/C5W2QwZrt-Z/",
+            query: "",
+            fragment: "",
+            spoiler: SpoilerTags::Spoiler
+        })
     );
     assert_eq!(
         extracted.next(),
-        Some(("/t/ZPRTX3AwH/", "", SpoilerTags::None))
+        Some(ExtractedLink {
+            host: "www.tiktok.com",
+            path: "/t/ZPRTX3AwH/",
+            query: "",
+            fragment: "top",
+            spoiler: SpoilerTags::None
+        })
     );
 
     assert!(extracted.next().is_none());
 }
+
+#[test]
+fn default_query_policy() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let [tweet, instagram, _] = &config.passes[..] else {
+        panic!("example config should have three passes");
+    };
+    let policy = |default_query| ApplyOptions {
+        default_query,
+        ..ApplyOptions::default()
+    };
+
+    // Instagram doesn't set `keep_query`, so it follows the policy
+    let content = "https://www.instagram.com/p/C5W2QwZrt-Z/?igsh=abc";
+    assert_eq!(
+        instagram
+            .apply(content, policy(QueryPolicy::KeepAll))
+            .as_deref(),
+        Some("[`Instagram Post`](https://ddinstagram.com/p/C5W2QwZrt-Z/?igsh=abc)")
+    );
+    assert_eq!(
+        instagram
+            .apply(content, policy(QueryPolicy::DropAll))
+            .as_deref(),
+        Some("[`Instagram Post`](https://ddinstagram.com/p/C5W2QwZrt-Z/)")
+    );
+
+    // Tweet sets `keep_query = []`, which wins over the policy
+    let content = "https://x.com/user/status/1?s=46";
+    assert_eq!(
+        tweet
+            .apply(content, policy(QueryPolicy::KeepAll))
+            .as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/user/status/1)")
+    );
+}
+
+#[test]
+fn empty_query() {
+    let keeping = |keep_query: &str| -> Pass {
+        let source = format!(
+            r#"
+            label = "Tweet"
+            regex = "https://x\\.com"
+            stem = "https://vxtwitter.com"
+            {keep_query}
+            "#
+        );
+        toml::from_str(&source).unwrap()
+    };
+    let fixed = Some("[`Tweet`](https://vxtwitter.com/user/status/1)");
+
+    // No query to keep means no `?`, whatever the pass keeps
+    for keep_query in ["", "keep_query = []", "keep_query = [\"t\"]"] {
+        let pass = keeping(keep_query);
+        let content = "https://x.com/user/status/1";
+        assert_eq!(
+            pass.apply(content, ApplyOptions::default()).as_deref(),
+            fixed
+        );
+    }
+
+    // Including when none of its keys are there
+    let pass = keeping("keep_query = [\"t\"]");
+    let content = "https://x.com/user/status/1?s=46";
+    assert_eq!(
+        pass.apply(content, ApplyOptions::default()).as_deref(),
+        fixed
+    );
+}
+
+#[test]
+fn stem_routes() {
+    let pass: Pass = toml::from_str(
+        r#"
+        label = "Tweet"
+        regex = "https://x\\.com"
+        stem = "https://vxtwitter.com"
+
+        [[route]]
+        path = "^/i/spaces/"
+        stem = "https://fixupx.com"
+        "#,
+    )
+    .unwrap();
+
+    let content = "https://x.com/user/status/1 https://x.com/i/spaces/1";
+    assert_eq!(
+        pass.apply(
+            content,
+            ApplyOptions {
+                default_query: QueryPolicy::DropAll,
+                ..ApplyOptions::default()
+            }
+        ).as_deref(),
+        Some(
+            "[`Tweet`](https://vxtwitter.com/user/status/1) [`Tweet`](https://fixupx.com/i/spaces/1)"
+        )
+    );
+}
+
+#[test]
+fn shortest_stem() {
+    let source = r#"
+        label = "Tweet"
+        regex = "https://x\\.com"
+        stem = "https://vxtwitter.com"
+
+        [[route]]
+        path = "/status/"
+        stem = "https://fxtwitter.com"
+
+        [[route]]
+        path = "/status/"
+        stem = "https://fixvx.com"
+        "#;
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        ..ApplyOptions::default()
+    };
+
+    // The first matching route by default...
+    let pass: Pass = toml::from_str(source).unwrap();
+    let content = "https://x.com/user/status/1";
+    assert_eq!(
+        pass.apply(content, options).as_deref(),
+        Some("[`Tweet`](https://fxtwitter.com/user/status/1)")
+    );
+
+    // ...or whichever is shortest, including the pass's own stem
+    let source = format!("stem_selection = \"shortest\"\n{source}");
+    let pass: Pass = toml::from_str(&source).unwrap();
+    assert_eq!(
+        pass.apply(content, options).as_deref(),
+        Some("[`Tweet`](https://fixvx.com/user/status/1)")
+    );
+    let content = "https://x.com/user/likes";
+    assert_eq!(
+        pass.apply(content, options).as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/user/likes)")
+    );
+}
+
+#[test]
+fn spoiler_spans() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let spoilers = |content| {
+        config.passes[0]
+            .extract(content)
+            .map(|link| link.spoiler)
+            .collect::<Vec<_>>()
+    };
+
+    // Links anywhere in a spoiler are hidden, not just ones touching the tags
+    assert_eq!(
+        spoilers("text ||more text https://x.com/a more|| end"),
+        [SpoilerTags::Spoiler]
+    );
+    assert_eq!(
+        spoilers("||spoiler|| https://x.com/a and ||https://x.com/b ||"),
+        [SpoilerTags::None, SpoilerTags::Spoiler]
+    );
+    assert_eq!(spoilers("||https://x.com/a"), [SpoilerTags::Mismatched]);
+
+    // A spoiler closing right before the next one opens doesn't swallow it
+    assert_eq!(
+        spoilers("||https://x.com/a || ||https://x.com/b ||"),
+        [SpoilerTags::Spoiler, SpoilerTags::Spoiler]
+    );
+    assert_eq!(
+        spoilers("https://x.com/a ||https://x.com/b ||"),
+        [SpoilerTags::None, SpoilerTags::Spoiler]
+    );
+}
+
+#[test]
+fn fire_count() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let counts = || {
+        config
+            .passes
+            .iter()
+            .map(Pass::fire_count)
+            .collect::<Vec<_>>()
+    };
+
+    Pass::record_fires(&config.passes, "no links here", ApplyOptions::default());
+    assert_eq!(counts(), [0, 0, 0]);
+
+    // Rewriting alone doesn't count, only replies that are recorded
+    let content = "https://x.com/a/status/1 https://x.com/b/status/2";
+    Pass::apply_all(&config.passes, content, ApplyOptions::default());
+    assert_eq!(counts(), [0, 0, 0]);
+    Pass::record_fires(&config.passes, content, ApplyOptions::default());
+    Pass::record_fires(&config.passes, content, ApplyOptions::default());
+    assert_eq!(counts(), [2, 0, 0]);
+
+    // Only the pass of the first link goes into a first-only reply
+    let content = "https://www.instagram.com/p/a/ https://x.com/a/status/1";
+    let first_only = ApplyOptions {
+        rewrite_mode: RewriteMode::FirstOnly,
+        ..ApplyOptions::default()
+    };
+    Pass::record_fires(&config.passes, content, first_only);
+    assert_eq!(counts(), [3, 0, 0]);
+}
+
+#[test]
+fn whole_reply_spoilers() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let options = ApplyOptions {
+        spoilers: SpoilerPropagation::WholeReply,
+        ..ApplyOptions::default()
+    };
+
+    let mixed = "https://x.com/a/status/1 ||https://www.instagram.com/p/b/ ||";
+    assert_eq!(
+        Pass::apply_all(&config.passes, mixed, options).fixed(),
+        Some("||[`Tweet`](https://vxtwitter.com/a/status/1) [`Instagram Post`](https://ddinstagram.com/p/b/)||")
+    );
+
+    let plain = "https://x.com/a/status/1";
+    assert_eq!(
+        Pass::apply_all(&config.passes, plain, options).fixed(),
+        Some("[`Tweet`](https://vxtwitter.com/a/status/1)")
+    );
+}
+
+#[test]
+fn false_positives() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    for pass in &config.passes {
+        assert_eq!(pass.false_positives().count(), 0, "{}", pass.label);
+    }
+
+    let broad: Pass = toml::from_str(
+        r#"
+        label = "Anything"
+        regex = "\\S*"
+        stem = "https://example.com"
+        "#,
+    )
+    .unwrap();
+    assert!(broad.false_positives().count() > 0);
+}
+
+#[test]
+fn skip_code() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let paths = |content| {
+        config.passes[0]
+            .extract(content)
+            .map(|link| link.path)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        paths("try `curl https://x.com/a/status/1` and https://x.com/b/status/2"),
+        ["/b/status/2"]
+    );
+    assert_eq!(
+        paths("```\nhttps://x.com/a/status/1\n```\nhttps://x.com/b/status/2"),
+        ["/b/status/2"]
+    );
+    assert_eq!(
+        paths("`` a ` https://x.com/a/status/1 ``"),
+        Vec::<&str>::new()
+    );
+    assert_eq!(
+        paths("unclosed ` https://x.com/a/status/1"),
+        ["/a/status/1"]
+    );
+}
+
+#[test]
+fn apply_into_matches_apply() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let inputs = [
+        "",
+        "no links here",
+        "https://x.com/a/status/1?s=46",
+        "https://x.com/a/status/1 https://twitter.com/b/status/2#top",
+        "||https://www.instagram.com/p/b/ || https://www.tiktok.com/t/c/ `https://x.com/d`",
+    ];
+    let options = [
+        ApplyOptions::default(),
+        ApplyOptions {
+            default_query: QueryPolicy::DropAll,
+            spoilers: SpoilerPropagation::WholeReply,
+            separator: "\n",
+            ..ApplyOptions::default()
+        },
+    ];
+
+    for content in inputs {
+        for options in options {
+            let mut out = "existing ".to_owned();
+            let mut expected = out.clone();
+            let mut patches = Vec::new();
+            for pass in &config.passes {
+                let fired = pass.apply_into(content, options, &mut out);
+                let patched = pass.apply(content, options);
+                assert_eq!(fired, patched.is_some(), "{content:?}");
+                expected.push_str(patched.as_deref().unwrap_or_default());
+                patches.extend(patched);
+            }
+            assert_eq!(out, expected, "{content:?}");
+
+            let all = Pass::apply_all(&config.passes, content, options);
+            let joined = patches.join(options.separator);
+            let spoiled = all.fixed().map(|all| {
+                let inner = all.strip_prefix("||").and_then(|a| a.strip_suffix("||"));
+                inner
+                    .filter(|_| options.spoilers == SpoilerPropagation::WholeReply)
+                    .unwrap_or(all)
+            });
+            assert_eq!(
+                spoiled,
+                (!joined.is_empty()).then_some(joined.as_str()),
+                "{content:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn show_id() {
+    let pass: Pass = toml::from_str(
+        r#"
+        label = "Tweet"
+        regex = "https://x\\.com"
+        stem = "https://vxtwitter.com"
+        show_id = true
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        pass.apply("https://x.com/a/status/123", ApplyOptions::default())
+            .as_deref(),
+        Some("[`Tweet 123`](https://vxtwitter.com/a/status/123)")
+    );
+    assert_eq!(
+        pass.apply("https://x.com/a/status/123/", ApplyOptions::default())
+            .as_deref(),
+        Some("[`Tweet 123`](https://vxtwitter.com/a/status/123/)")
+    );
+    assert_eq!(
+        pass.apply("https://x.com/rustlang", ApplyOptions::default())
+            .as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/rustlang)")
+    );
+}
+
+#[test]
+fn join_separator() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        separator: "\n",
+        ..ApplyOptions::default()
+    };
+
+    assert_eq!(
+        Pass::apply_all(&config.passes, "https://x.com/a/status/1", options).fixed(),
+        Some("[`Tweet`](https://vxtwitter.com/a/status/1)")
+    );
+
+    let content =
+        "https://x.com/a/status/1 https://x.com/b/status/2 https://www.instagram.com/p/c/";
+    assert_eq!(
+        Pass::apply_all(&config.passes, content, options).fixed(),
+        Some(
+            "[`Tweet`](https://vxtwitter.com/a/status/1)\n[`Tweet`](https://vxtwitter.com/b/status/2)\n[`Instagram Post`](https://ddinstagram.com/p/c/)"
+        )
+    );
+}
+
+#[test]
+fn host_stems() {
+    let pass: Pass = toml::from_str(
+        r#"
+        label = "Tweet"
+        regex = "https://(?:x|twitter|mobile\\.twitter)\\.com"
+
+        [stem]
+        "twitter.com" = "https://vxtwitter.com"
+        "x.com" = "https://fixvx.com"
+        default = "https://fxtwitter.com"
+        "#,
+    )
+    .unwrap();
+
+    let content = "https://twitter.com/a/status/1 https://x.com/b/status/2 https://mobile.twitter.com/c/status/3";
+    assert_eq!(
+        pass.apply(
+            content,
+            ApplyOptions {
+                default_query: QueryPolicy::DropAll,
+                ..ApplyOptions::default()
+            }
+        )
+        .as_deref(),
+        Some(
+            "[`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://fixvx.com/b/status/2) [`Tweet`](https://fxtwitter.com/c/status/3)"
+        )
+    );
+}
+
+#[test]
+fn no_match() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let tweet = &config.passes[0];
+
+    assert_eq!(tweet.apply("", ApplyOptions::default()), None);
+    assert_eq!(tweet.apply("no links here", ApplyOptions::default()), None);
+    assert_eq!(
+        tweet.apply("https://www.instagram.com/p/a/", ApplyOptions::default()),
+        None
+    );
+
+    // Matches that get filtered out still don't produce anything
+    assert_eq!(
+        tweet.apply("`https://x.com/a/status/1`", ApplyOptions::default()),
+        None
+    );
+    assert_eq!(tweet.fire_count(), 0);
+}
+
+#[test]
+fn priority() {
+    let passes: Vec<Pass> = ["a", "b", "c", "d"]
+        .into_iter()
+        .zip([0, 5, 0, -1])
+        .map(|(host, priority)| {
+            let source = format!(
+                "label = \"{host}\"\nregex = \"https://{host}\\\\.com\"\nstem = \"https://{host}.fixed\"\npriority = {priority}"
+            );
+            toml::from_str(&source).unwrap()
+        })
+        .collect();
+
+    let content = "https://d.com/1 https://c.com/1 https://b.com/1 https://a.com/1";
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        ..ApplyOptions::default()
+    };
+    assert_eq!(
+        Pass::apply_all(&passes, content, options).fixed(),
+        Some("[`b`](https://b.fixed/1) [`a`](https://a.fixed/1) [`c`](https://c.fixed/1) [`d`](https://d.fixed/1)")
+    );
+}
+
+#[test]
+fn pattern() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    assert_eq!(
+        config.passes[0].pattern(),
+        "(?:^|\\s|(?P<lead>[:(\\[<\"`]))(?P<sp_open>\\|\\||)(?P<core>https://(?:x|twitter)\\.com)(?P<path>/(?:[^\\s|]|\\|[^\\s|])+)(?P<sp_close>\\|\\||)"
+    );
+}
+
+#[test]
+fn rewrite_outcome() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let rewrite = |content| Pass::apply_all(&config.passes, content, ApplyOptions::default());
+
+    assert_eq!(rewrite("no links here"), Rewrite::NoLinks);
+    assert_eq!(rewrite("`https://x.com/a/status/1`"), Rewrite::Filtered);
+    assert_eq!(
+        rewrite("https://x.com/a/status/1"),
+        Rewrite::Fixed("[`Tweet`](https://vxtwitter.com/a/status/1)".to_owned())
+    );
+}
+
+#[test]
+fn leading_punctuation() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let rewrite = |content| Pass::apply_all(&config.passes, content, ApplyOptions::default());
+    let fixed = Rewrite::Fixed("[`Tweet`](https://vxtwitter.com/a/status/1)".to_owned());
+
+    assert_eq!(rewrite("see:https://x.com/a/status/1"), fixed);
+    assert_eq!(rewrite("first\nhttps://x.com/a/status/1"), fixed);
+
+    // Brackets around the link aren't part of it
+    assert_eq!(rewrite("(https://x.com/a/status/1)"), fixed);
+    assert_eq!(rewrite("<https://x.com/a/status/1>"), fixed);
+
+    // Spoiler tags inside the brackets are still picked up
+    let links: Vec<_> = config.passes[0]
+        .extract("(||https://x.com/a)")
+        .map(|link| (link.path, link.spoiler))
+        .collect();
+    assert_eq!(links, [("/a", SpoilerTags::Mismatched)]);
+
+    // But other text right before the link still rules it out
+    assert_eq!(rewrite("foohttps://x.com/a/status/1"), Rewrite::NoLinks);
+}
+
+#[test]
+fn trailing_punctuation() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let links = |content| {
+        config.passes[0]
+            .extract(content)
+            .map(|link| (link.path, link.query))
+            .collect::<Vec<_>>()
+    };
+
+    // A closing bracket ends the link, so the next one can start right after
+    let both = [("/a/status/1", ""), ("/b/status/2", "")];
+    assert_eq!(
+        links("(https://x.com/a/status/1)(https://x.com/b/status/2)"),
+        both
+    );
+    assert_eq!(
+        links("[https://x.com/a/status/1](https://x.com/b/status/2)"),
+        both
+    );
+    assert_eq!(
+        links("\"https://x.com/a/status/1\",\"https://x.com/b/status/2\""),
+        both
+    );
+
+    // Punctuation ending a sentence isn't part of the link
+    for content in [
+        "https://x.com/a/status/1.",
+        "see https://x.com/a/status/1, then",
+        "https://x.com/a/status/1!",
+        "(see https://x.com/a/status/1).",
+        "https://x.com/a/status/1`",
+    ] {
+        assert_eq!(links(content), [("/a/status/1", "")], "{content}");
+    }
+    assert_eq!(
+        links("https://x.com/a/status/1?s=20."),
+        [("/a/status/1", "s=20")]
+    );
+
+    // Unless the link opened the bracket itself
+    assert_eq!(links("https://x.com/a_(b)"), [("/a_(b)", "")]);
+    assert_eq!(links("(https://x.com/a_(b))"), [("/a_(b)", "")]);
+}
+
+#[test]
+fn unbalanced_regex() {
+    // Closing the group early leaves matches without a host or path
+    let pass: Pass = toml::from_str(
+        r#"
+        label = "Tweet"
+        regex = "https://x\\.com)|(?:foo"
+        stem = "https://vxtwitter.com"
+        "#,
+    )
+    .unwrap();
+
+    for content in ["https://x.com/a/status/1", "foo/a/status/1"] {
+        assert!(pass.kind.regex().is_match(content));
+        assert_eq!(pass.extract(content).count(), 0);
+        assert_eq!(pass.apply(content, ApplyOptions::default()), None);
+    }
+}
+
+#[test]
+fn regex_flags() {
+    let load = |flags: &str| {
+        toml::from_str::<Pass>(&format!(
+            "label = \"Tweet\"\nregex = \"https://x\\\\.com\"\nstem = \"https://vxtwitter.com\"\n{flags}"
+        ))
+    };
+
+    let content = "HTTPS://X.com/a/status/1";
+    let plain = load("").unwrap();
+    assert_eq!(plain.extract(content).count(), 0);
+
+    let insensitive = load("flags = \"i\"").unwrap();
+    assert_eq!(
+        insensitive.pattern(),
+        "(?:^|\\s|(?P<lead>[:(\\[<\"`]))(?P<sp_open>\\|\\||)(?P<core>(?i)https://x\\.com)(?P<path>/(?:[^\\s|]|\\|[^\\s|])+)(?P<sp_close>\\|\\||)"
+    );
+    let paths: Vec<_> = insensitive.extract(content).map(|link| link.path).collect();
+    assert_eq!(paths, ["/a/status/1"]);
+
+    assert!(load("flags = \"q\"").is_err());
+}
+
+#[test]
+fn split_reply() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        ..ApplyOptions::default()
+    };
+    let content = "https://x.com/a/status/1 https://x.com/b/status/2 https://x.com/c/status/3";
+
+    let whole = Pass::apply_all(&config.passes, content, options);
+    let chunks = Pass::apply_all_split(&config.passes, content, options, 2000);
+    assert_eq!(chunks, [whole.fixed().unwrap()]);
+
+    let chunks = Pass::apply_all_split(&config.passes, content, options, 90);
+    assert_eq!(
+        chunks,
+        [
+            "[`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/b/status/2)",
+            "[`Tweet`](https://vxtwitter.com/c/status/3)",
+        ]
+    );
+
+    // A link longer than the limit still gets a chunk
+    let chunks = Pass::apply_all_split(&config.passes, content, options, 10);
+    assert_eq!(chunks.len(), 3);
+
+    let spoiled = ApplyOptions {
+        spoilers: SpoilerPropagation::WholeReply,
+        ..options
+    };
+    let content = "||https://x.com/a/status/1 https://x.com/b/status/2 ||";
+    let chunks = Pass::apply_all_split(&config.passes, content, spoiled, 90);
+    assert_eq!(
+        chunks,
+        [
+            "||[`Tweet`](https://vxtwitter.com/a/status/1)||",
+            "||[`Tweet`](https://vxtwitter.com/b/status/2)||",
+        ]
+    );
+
+    assert!(Pass::apply_all_split(&config.passes, "no links", options, 2000).is_empty());
+}
+
+#[test]
+fn spoiler_render() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let content = "||https://x.com/a/status/1 || https://x.com/b/status/2";
+    let render = |spoilers, spoiler_render| {
+        let options = ApplyOptions {
+            default_query: QueryPolicy::DropAll,
+            spoilers,
+            spoiler_render,
+            ..ApplyOptions::default()
+        };
+        Pass::apply_all(&config.passes, content, options)
+            .fixed()
+            .map(str::to_owned)
+    };
+
+    assert_eq!(
+        render(SpoilerPropagation::PerLink, SpoilerRender::DiscordSpoiler).as_deref(),
+        Some("||[`Tweet`](https://vxtwitter.com/a/status/1) || [`Tweet`](https://vxtwitter.com/b/status/2)")
+    );
+    assert_eq!(
+        render(SpoilerPropagation::PerLink, SpoilerRender::EmojiPrefix).as_deref(),
+        Some("⚠️ [`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/b/status/2)")
+    );
+    assert_eq!(
+        render(SpoilerPropagation::WholeReply, SpoilerRender::EmojiPrefix).as_deref(),
+        Some("⚠️ [`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/b/status/2)")
+    );
+
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        spoiler_render: SpoilerRender::EmojiPrefix,
+        spoiler_emoji: "🙈",
+        ..ApplyOptions::default()
+    };
+    assert_eq!(
+        Pass::apply_all(&config.passes, "||https://x.com/a/status/1 ||", options).fixed(),
+        Some("🙈 [`Tweet`](https://vxtwitter.com/a/status/1)")
+    );
+}
+
+#[test]
+fn formatting_in_path() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        ..ApplyOptions::default()
+    };
+    let fixed = |content| {
+        Pass::apply_all(&config.passes, content, options)
+            .fixed()
+            .map(str::to_owned)
+    };
+
+    assert_eq!(
+        fixed("https://x.com/**user**/status/123").as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/user/status/123)")
+    );
+    assert_eq!(
+        fixed("https://x.com/user/sta\u{200B}tus/123\u{200B}").as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/user/status/123)")
+    );
+
+    // Underscores are part of usernames
+    assert_eq!(
+        fixed("https://x.com/__user__/status/123").as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/__user__/status/123)")
+    );
+
+    let tweet = &config.passes[0];
+    let link = tweet
+        .extract("https://x.com/~~user~~/status/123")
+        .next()
+        .unwrap();
+    assert_eq!(
+        tweet.normalized_url(&link),
+        "https://vxtwitter.com/user/status/123"
+    );
+}
+
+#[test]
+fn first_only() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        rewrite_mode: RewriteMode::FirstOnly,
+        ..ApplyOptions::default()
+    };
+    let fixed = |content| {
+        Pass::apply_all(&config.passes, content, options)
+            .fixed()
+            .map(str::to_owned)
+    };
+
+    assert_eq!(
+        fixed("https://x.com/a/status/1").as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/a/status/1)")
+    );
+    assert_eq!(
+        fixed("https://www.instagram.com/p/b/ https://x.com/a/status/1").as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/a/status/1) +1 more link")
+    );
+    assert_eq!(
+        fixed("https://x.com/a/status/1 https://x.com/b/status/2 https://www.tiktok.com/t/c/")
+            .as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/a/status/1) +2 more links")
+    );
+
+    assert_eq!(
+        Pass::apply_all(
+            &config.passes,
+            "try `curl https://x.com/a/status/1`",
+            options
+        ),
+        Rewrite::Filtered
+    );
+    assert_eq!(
+        Pass::apply_all(&config.passes, "no links", options),
+        Rewrite::NoLinks
+    );
+}
+
+#[test]
+fn capturing_core() {
+    let pass: Pass = toml::from_str(
+        "label = \"Tweet\"\nregex = \"https://(x|twitter)\\\\.com\"\nstem = \"https://vxtwitter.com\"",
+    )
+    .unwrap();
+
+    let links: Vec<_> = pass
+        .extract("https://twitter.com/a/status/1?s=46 ||https://x.com/b/status/2 ||")
+        .collect();
+    assert_eq!(
+        links,
+        [
+            ExtractedLink {
+                host: "twitter.com",
+                path: "/a/status/1",
+                query: "s=46",
+                fragment: "",
+                spoiler: SpoilerTags::None,
+            },
+            ExtractedLink {
+                host: "x.com",
+                path: "/b/status/2",
+                query: "",
+                fragment: "",
+                spoiler: SpoilerTags::Spoiler,
+            },
+        ]
+    );
+}
+
+#[test]
+fn skip_media() {
+    let load = |extra: &str| {
+        toml::from_str::<Pass>(&format!(
+            "label = \"Tweet\"\nregex = \"https://x\\\\.com\"\nstem = \"https://vxtwitter.com\"\n{extra}"
+        ))
+        .unwrap()
+    };
+    let content = "https://x.com/a/status/1/photo/1 https://x.com/a/media/b.JPG?name=large https://x.com/a/status/2";
+
+    let paths = |pass: &Pass| {
+        pass.extract(content)
+            .map(|link| link.path.to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(paths(&load("")).len(), 3);
+    assert_eq!(paths(&load("skip_media = true")), ["/a/status/2"]);
+
+    // Skipping every link filters the message, like links in code do
+    let pass = load("skip_media = true");
+    assert_eq!(
+        pass.rewrite("https://x.com/a/video.mp4", ApplyOptions::default()),
+        Rewrite::Filtered
+    );
+}
+
+#[test]
+fn any_scheme() {
+    let load = |extra: &str| {
+        toml::from_str::<Pass>(&format!(
+            "label = \"Tweet\"\nregex = \"https://x\\\\.com\"\nstem = \"https://vxtwitter.com\"\n{extra}"
+        ))
+    };
+    let rewrite = |pass: &Pass, content| pass.rewrite(content, ApplyOptions::default());
+    let fixed = Rewrite::Fixed("[`Tweet`](https://vxtwitter.com/a/status/1)".to_owned());
+
+    let plain = load("").unwrap();
+    assert_eq!(rewrite(&plain, "x.com/a/status/1"), Rewrite::NoLinks);
+    assert_eq!(rewrite(&plain, "http://x.com/a/status/1"), Rewrite::NoLinks);
+
+    let any = load("any_scheme = true").unwrap();
+    assert_eq!(
+        any.pattern(),
+        "(?:^|\\s|(?P<lead>[:(\\[<\"`]))(?P<sp_open>\\|\\||)(?P<core>(?:https?://)?x\\.com)(?P<path>/(?:[^\\s|]|\\|[^\\s|])+)(?P<sp_close>\\|\\||)"
+    );
+    for content in [
+        "x.com/a/status/1",
+        "see x.com/a/status/1",
+        "http://x.com/a/status/1",
+        "https://x.com/a/status/1",
+    ] {
+        assert_eq!(rewrite(&any, content), fixed, "{content:?}");
+    }
+    let hosts: Vec<_> = any
+        .extract("x.com/a/status/1")
+        .map(|link| link.host)
+        .collect();
+    assert_eq!(hosts, ["x.com"]);
+
+    // Only a whole host is matched without a scheme
+    assert_eq!(rewrite(&any, "fixupx.com/a/status/1"), Rewrite::NoLinks);
+
+    // There has to be a scheme to make optional
+    let schemeless = toml::from_str::<Pass>(
+        "label = \"Tweet\"\nregex = \"x\\\\.com\"\nstem = \"https://vxtwitter.com\"\nany_scheme = true",
+    );
+    assert!(schemeless.is_err());
+}
+
+#[test]
+fn random_unicode() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+
+    // Pieces of links and markup mixed with multi-byte characters, so any
+    // slicing next to them would land inside a character
+    let pieces = [
+        "https://x.com",
+        "https://www.instagram.com",
+        "/a/status/1",
+        "/p/é/",
+        "?s=20&t=ü",
+        "#top",
+        "||",
+        "`",
+        "```",
+        "**",
+        "~~",
+        " ",
+        "\n",
+        "\u{200B}",
+        "\u{FEFF}",
+        "\u{202E}",
+        "e\u{301}",
+        "é",
+        "ア",
+        "🎉",
+        "👩‍👩‍👧",
+    ];
+
+    // SplitMix64, so every run sees the same inputs
+    let mut state = 0u64;
+    let mut next = move || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+
+    let spoiler_options = ApplyOptions {
+        spoilers: SpoilerPropagation::WholeReply,
+        spoiler_render: SpoilerRender::EmojiPrefix,
+        ..ApplyOptions::default()
+    };
+    let first_options = ApplyOptions {
+        rewrite_mode: RewriteMode::FirstOnly,
+        separator: "\n",
+        ..ApplyOptions::default()
+    };
+
+    for _ in 0..2000 {
+        let mut content = String::new();
+        for _ in 0..next() % 24 {
+            let roll = next();
+            if roll % 4 == 0 {
+                // Anything at all, not just the pieces
+                content.extend(char::from_u32((roll >> 8) as u32 % 0x11_0000));
+            } else {
+                content.push_str(pieces[(roll >> 8) as usize % pieces.len()]);
+            }
+        }
+
+        for options in [ApplyOptions::default(), spoiler_options, first_options] {
+            let _ = Pass::apply_all(&config.passes, &content, options);
+            let _ = Pass::apply_all_split(&config.passes, &content, options, 60);
+        }
+        for pass in &config.passes {
+            let _ = pass.rewrite(&content, ApplyOptions::default());
+            for link in pass.extract(&content) {
+                let _ = pass.normalized_url(&link);
+            }
+        }
+    }
+}
+
+#[test]
+fn dedup_across_passes() {
+    let config: Config = toml::from_str(
+        &[
+            include_str!("../config.example.toml"),
+            r#"
+        [[pass]]
+        label = "X"
+        regex = "https://x\\.com"
+        stem = "https://fixupx.com"
+        "#,
+        ]
+        .concat(),
+    )
+    .unwrap();
+    let content = "https://x.com/a/status/1 https://twitter.com/a/status/2";
+
+    // Both passes match the x.com link by default
+    let rewrite = Pass::apply_all(&config.passes, content, ApplyOptions::default());
+    assert_eq!(
+        rewrite,
+        Rewrite::Fixed(
+            "[`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/a/status/2) [`X`](https://fixupx.com/a/status/1)"
+                .to_owned()
+        )
+    );
+
+    let options = ApplyOptions {
+        dedup_across_passes: true,
+        ..ApplyOptions::default()
+    };
+    let fixed =
+        "[`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/a/status/2)";
+    assert_eq!(
+        Pass::apply_all(&config.passes, content, options),
+        Rewrite::Fixed(fixed.to_owned())
+    );
+    Pass::record_fires(&config.passes, content, options);
+    assert_eq!(config.passes[0].fire_count(), 1);
+    assert_eq!(config.passes[3].fire_count(), 0);
+    assert_eq!(
+        Pass::apply_all_split(&config.passes, content, options, 2000),
+        [fixed]
+    );
+
+    // Priority decides which pass gets the link
+    let [tweet, _, _, x] = &config.passes[..] else {
+        panic!("Expected four passes");
+    };
+    assert_eq!(
+        Pass::apply_all(&[x, tweet], content, options),
+        Rewrite::Fixed(
+            "[`X`](https://fixupx.com/a/status/1) [`Tweet`](https://vxtwitter.com/a/status/2)"
+                .to_owned()
+        )
+    );
+
+    let first_only = ApplyOptions {
+        rewrite_mode: RewriteMode::FirstOnly,
+        ..options
+    };
+    assert_eq!(
+        Pass::apply_all(&config.passes, content, first_only),
+        Rewrite::Fixed("[`Tweet`](https://vxtwitter.com/a/status/1) +1 more link".to_owned())
+    );
+}
+
+#[test]
+fn substitute() {
+    let load = |extra: &str| {
+        toml::from_str::<Pass>(&format!(
+            "label = \"Abbreviations\"\nkind = \"substitute\"\n{extra}"
+        ))
+    };
+    let pass = load("regex = \"\\\\bbrb\\\\b\"\nreplacement = \"be right back\"").unwrap();
+    assert!(pass.kind.stem().is_none());
+    assert_eq!(
+        pass.rewrite("ok brb, food", ApplyOptions::default()),
+        Rewrite::Fixed("ok be right back, food".to_owned())
+    );
+    Pass::record_fires(&[&pass], "ok brb, food", ApplyOptions::default());
+    assert_eq!(pass.fire_count(), 1);
+    assert_eq!(
+        pass.rewrite("brbrb", ApplyOptions::default()),
+        Rewrite::NoLinks
+    );
+
+    // Groups can be used in the replacement, and flags apply
+    let pass =
+        load("regex = \"(?P<n>\\\\d+)k\\\\b\"\nflags = \"i\"\nreplacement = \"${n},000\"").unwrap();
+    assert_eq!(
+        pass.apply("5K or 10k", ApplyOptions::default()).as_deref(),
+        Some("5,000 or 10,000")
+    );
+
+    // The replaced message comes after any links
+    let mut config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    config
+        .passes
+        .push(load("regex = \"brb\"\nreplacement = \"be right back\"").unwrap());
+    let options = ApplyOptions::default();
+    let content = "brb https://x.com/a/status/1";
+    assert_eq!(
+        Pass::apply_all(&config.passes, content, options),
+        Rewrite::Fixed(
+            "[`Tweet`](https://vxtwitter.com/a/status/1) be right back https://x.com/a/status/1"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        Pass::apply_all_split(&config.passes, content, options, 60),
+        [
+            "[`Tweet`](https://vxtwitter.com/a/status/1)",
+            "be right back https://x.com/a/status/1"
+        ]
+    );
+    let first_only = ApplyOptions {
+        rewrite_mode: RewriteMode::FirstOnly,
+        ..options
+    };
+    assert_eq!(
+        Pass::apply_all(&config.passes, "brb", first_only),
+        Rewrite::Fixed("be right back".to_owned())
+    );
+
+    // Each kind needs what it rewrites with
+    assert!(load("regex = \"brb\"").is_err());
+    assert!(toml::from_str::<Pass>("label = \"Tweet\"\nregex = \"https://x\\\\.com\"").is_err());
+}
+
+#[test]
+fn group_by_pass() {
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        group_by_pass: true,
+        ..ApplyOptions::default()
+    };
+
+    let content = "https://x.com/a/status/1 https://www.instagram.com/p/b https://x.com/c/status/2";
+    assert_eq!(
+        Pass::apply_all(&config.passes, content, options),
+        Rewrite::Fixed(
+            "**Tweet**\n[`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/c/status/2)\n**Instagram Post**\n[`Instagram Post`](https://ddinstagram.com/p/b)"
+                .to_owned()
+        )
+    );
+    Pass::record_fires(&config.passes, content, options);
+    assert!(config.passes[..2].iter().all(|pass| pass.fire_count() == 1));
+
+    // Links from just one pass stay flat
+    let content = "https://x.com/a/status/1 https://x.com/c/status/2";
+    assert_eq!(
+        Pass::apply_all(&config.passes, content, options),
+        Rewrite::Fixed(
+            "[`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/c/status/2)"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn skip_already_fixed() {
+    // This also matches its own stem
+    let pass: Pass = toml::from_str(
+        "label = \"Tweet\"\nregex = \"https://\\\\w*twitter\\\\.com\"\nstem = \"https://vxtwitter.com\"\nkeep_query = []",
+    )
+    .unwrap();
+    let options = ApplyOptions::default();
+
+    let mirrored = "https://vxtwitter.com/a/status/1";
+    assert_eq!(pass.rewrite(mirrored, options), Rewrite::Filtered);
+    assert_eq!(
+        Pass::apply_all(&[&pass], mirrored, options),
+        Rewrite::Filtered
+    );
+    assert_eq!(
+        pass.apply(
+            "https://twitter.com/a/status/1 https://vxtwitter.com/b/status/2",
+            options
+        )
+        .as_deref(),
+        Some("[`Tweet`](https://vxtwitter.com/a/status/1)")
+    );
+
+    // Other passes' stems don't count, so a pass can take another's output
+    let other: Pass = toml::from_str(
+        "label = \"Other\"\nregex = \"https://vxtwitter\\\\.com\"\nstem = \"https://d.vxtwitter.com\"",
+    )
+    .unwrap();
+    let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    let passes: Vec<_> = config.passes.iter().chain([&other]).collect();
+    assert_eq!(
+        Pass::apply_all(
+            &passes,
+            "https://x.com/a/status/1 https://vxtwitter.com/b/status/2",
+            options
+        ),
+        Rewrite::Fixed(
+            "[`Tweet`](https://vxtwitter.com/a/status/1) [`Other`](https://d.vxtwitter.com/b/status/2)"
+                .to_owned()
+        )
+    );
+
+    let refixing = ApplyOptions {
+        skip_already_fixed: false,
+        ..options
+    };
+    assert_eq!(
+        pass.rewrite(mirrored, refixing),
+        Rewrite::Fixed("[`Tweet`](https://vxtwitter.com/a/status/1)".to_owned())
+    );
+}
+
+#[test]
+fn min_path_segments() {
+    let pass: Pass = toml::from_str(
+        "label = \"Tweet\"\nregex = \"https://x\\\\.com\"\nstem = \"https://vxtwitter.com\"\nmin_path_segments = 2",
+    )
+    .unwrap();
+    let paths = |content| {
+        pass.extract(content)
+            .map(|link| link.path.to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    assert!(paths("https://x.com/user").is_empty());
+    assert!(paths("https://x.com/user/?s=20").is_empty());
+    assert!(paths("https://x.com//user//").is_empty());
+    assert_eq!(paths("https://x.com/user/status/1"), ["/user/status/1"]);
+    assert_eq!(
+        pass.rewrite("https://x.com/user", ApplyOptions::default()),
+        Rewrite::Filtered
+    );
+}
+
+#[test]
+fn link_type_label() {
+    let mut config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+    config.passes[0].link_type_label = true;
+    let options = ApplyOptions {
+        default_query: QueryPolicy::DropAll,
+        ..ApplyOptions::default()
+    };
+
+    // Once before the pass's links, and not before other passes' links
+    let content =
+        "https://x.com/a/status/1 https://x.com/b/status/2 https://www.instagram.com/p/c/";
+    let fixed = "Tweet fixed: [`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/b/status/2) [`Instagram Post`](https://ddinstagram.com/p/c/)";
+    assert_eq!(
+        Pass::apply_all(&config.passes, content, options),
+        Rewrite::Fixed(fixed.to_owned())
+    );
+    assert_eq!(
+        Pass::apply_all_split(&config.passes, content, options, 2000),
+        [fixed]
+    );
+    assert_eq!(
+        config.passes[0].apply(content, options).as_deref(),
+        Some("Tweet fixed: [`Tweet`](https://vxtwitter.com/a/status/1) [`Tweet`](https://vxtwitter.com/b/status/2)")
+    );
+}